@@ -5,17 +5,166 @@ use std::result;
 use proc_macro2::{Span, TokenStream};
 use quote::{quote, quote_spanned, ToTokens};
 use syn::{
-    punctuated::Punctuated, spanned::Spanned, Attribute, Data, DataStruct, DeriveInput, Expr,
-    ExprLit, Field, Fields, FieldsNamed, FieldsUnnamed, Ident, Index, Lit, LitStr, Meta,
-    MetaNameValue, Token, Variant,
+    parse::ParseStream,
+    parse_quote, parse_quote_spanned,
+    punctuated::Punctuated,
+    spanned::Spanned,
+    visit::{self, Visit},
+    Attribute, Data, DeriveInput, Expr, ExprLit, Field, Fields, FieldsNamed, FieldsUnnamed,
+    Generics, Ident, Index, Lit, LitStr, Meta, MetaNameValue, Token, Variant, WherePredicate,
 };
 
 // #[heap_size]
 const HEAP_IDENT: &str = "heap_size";
 // #[heap_size(with = "...")] Field attributes
 const HEAP_ATTR_WITH_IDENT: &str = "with";
+// #[heap_size(fn = "...")] Container and field attributes
+const HEAP_ATTR_FN_IDENT: &str = "fn";
+// #[heap_size(expr = "...")] Field attributes
+const HEAP_ATTR_EXPR_IDENT: &str = "expr";
+// #[heap_size(add = ...)] Field attributes
+const HEAP_ATTR_ADD_IDENT: &str = "add";
+// #[heap_size(active = "...")] Union attributes
+const HEAP_ATTR_ACTIVE_IDENT: &str = "active";
 // #[heap_size(skip)] Field attributes
 const HEAP_ATTR_SKIP_IDENT: &str = "skip";
+// #[heap_size(zero)] Field attributes
+const HEAP_ATTR_ZERO_IDENT: &str = "zero";
+// #[heap_size(exhaustive)] Container attributes
+const HEAP_ATTR_EXHAUSTIVE_IDENT: &str = "exhaustive";
+// #[heap_size(auto)] Container attributes
+const HEAP_ATTR_AUTO_IDENT: &str = "auto";
+
+// Types that are known to own heap memory in `#[heap_size(auto)]`.
+const AUTO_HEAP_TYPES: &[&str] = &[
+    "Vec",
+    "VecDeque",
+    "LinkedList",
+    "BinaryHeap",
+    "String",
+    "PathBuf",
+    "Cow",
+    "Box",
+    "Rc",
+    "Arc",
+    "HashMap",
+    "HashSet",
+    "BTreeMap",
+    "BTreeSet",
+    "Bytes",
+    "BytesMut",
+    "RepeatedField",
+    "SingularField",
+    "SingularPtrField",
+];
+// Types that are known to own no heap memory in `#[heap_size(auto)]`.
+const AUTO_POD_TYPES: &[&str] = &[
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "u128",
+    "usize",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "i128",
+    "isize",
+    "f32",
+    "f64",
+    "bool",
+    "char",
+    "NonZeroU8",
+    "NonZeroU16",
+    "NonZeroU32",
+    "NonZeroU64",
+    "NonZeroU128",
+    "NonZeroUsize",
+    "NonZeroI8",
+    "NonZeroI16",
+    "NonZeroI32",
+    "NonZeroI64",
+    "NonZeroI128",
+    "NonZeroIsize",
+    "AtomicBool",
+    "AtomicU8",
+    "AtomicU16",
+    "AtomicU32",
+    "AtomicU64",
+    "AtomicUsize",
+    "AtomicI8",
+    "AtomicI16",
+    "AtomicI32",
+    "AtomicI64",
+    "AtomicIsize",
+    "Duration",
+    "Instant",
+    "SystemTime",
+    "Ordering",
+    "PhantomData",
+    "PhantomPinned",
+];
+// #[heap_size(par)] Field attributes
+const HEAP_ATTR_PAR_IDENT: &str = "par";
+// #[heap_size(with_ctx = "...")] Field attributes
+const HEAP_ATTR_WITH_CTX_IDENT: &str = "with_ctx";
+// #[heap_size(shallow)] Field attributes
+const HEAP_ATTR_SHALLOW_IDENT: &str = "shallow";
+// #[heap_size(shared = "...")] Field attributes
+const HEAP_ATTR_SHARED_IDENT: &str = "shared";
+// #[heap_size(kind = "...")] Field attributes
+const HEAP_ATTR_KIND_IDENT: &str = "kind";
+// #[heap_size(bound = "...")] Container and field attributes
+const HEAP_ATTR_BOUND_IDENT: &str = "bound";
+// #[heap_size(crate = "...")] Container attributes
+const HEAP_ATTR_CRATE_IDENT: &str = "crate";
+// #[heap_size(remote = "...")] Container attributes
+const HEAP_ATTR_REMOTE_IDENT: &str = "remote";
+// Hints of attributes that must be followed by a value.
+const HEAP_ATTR_VALUE_HINTS: &[(&str, &str)] = &[
+    (
+        HEAP_ATTR_WITH_IDENT,
+        "a module path, `with = \"some::mod\"`",
+    ),
+    (
+        HEAP_ATTR_FN_IDENT,
+        "a function path, `fn = \"some::heap_size\"`",
+    ),
+    (
+        HEAP_ATTR_EXPR_IDENT,
+        "an expression, `expr = \"self.buf.len() * 2\"`",
+    ),
+    (
+        HEAP_ATTR_ADD_IDENT,
+        "a constant number of bytes, `add = 4096`",
+    ),
+    (
+        HEAP_ATTR_ACTIVE_IDENT,
+        "a method name, `active = \"active_field\"`",
+    ),
+    (
+        HEAP_ATTR_WITH_CTX_IDENT,
+        "a context type, `with_ctx = \"some::Context\"`",
+    ),
+    (
+        HEAP_ATTR_SHARED_IDENT,
+        "a mode, `shared = \"proportional\"`",
+    ),
+    (
+        HEAP_ATTR_BOUND_IDENT,
+        "where predicates, `bound = \"T: HeapSize\"`",
+    ),
+    (
+        HEAP_ATTR_CRATE_IDENT,
+        "a crate path, `crate = \"some::heapsz\"`",
+    ),
+    (
+        HEAP_ATTR_REMOTE_IDENT,
+        "a type path, `remote = \"other_crate::Type\"`",
+    ),
+    (HEAP_ATTR_KIND_IDENT, "a memory kind, `kind = \"mmap\"`"),
+];
 
 #[proc_macro_derive(HeapSize, attributes(heap_size))]
 pub fn heap(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
@@ -27,10 +176,7 @@ pub fn heap(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let tokens = match input.data {
         Data::Struct(..) => render_struct(input),
         Data::Enum(..) => render_enum(input),
-        Data::Union(..) => Err(syn::Error::new_spanned(
-            input,
-            "`Heap` can not be derived for a union",
-        )),
+        Data::Union(..) => render_union(input),
     };
     tokens.unwrap_or_else(syn::Error::into_compile_error).into()
 }
@@ -45,12 +191,66 @@ macro_rules! bail {
 enum HeapAttr {
     // #[heap_size] on a struct or enum.
     Container(Meta),
+    // #[heap_size(exhaustive)] on a struct or enum.
+    Exhaustive,
+    // #[heap_size(auto)] on a struct or enum.
+    Auto,
+    // #[heap_size(with = "")] or #[heap_size(fn = "")] on a container or a
+    // variant, the path is the function that returns the heap size.
+    ContainerWith(Meta, syn::Path),
+    // #[heap_size(active = "")] on a union.
+    UnionActive(Meta, Ident),
     // #[heap_size] on a field.
     Field,
     // #[heap_size(with = "")] on a field.
     FieldWith(Meta, LitStr),
+    // #[heap_size(fn = "")] on a field.
+    FieldFn(syn::Path),
+    // #[heap_size(expr = "")] on a field.
+    FieldExpr(LitStr, Expr),
     // #[heap_size(skip)] on a field.
     FieldSkip(Meta),
+    // #[heap_size(zero)] on a field.
+    FieldZero,
+    // #[heap_size(par)] on a field.
+    FieldPar,
+    // #[heap_size(with_ctx = "")] on a field.
+    FieldWithCtx(Meta, LitStr),
+    // #[heap_size(shallow)] on a field.
+    FieldShallow,
+    // #[heap_size(shared = "")] on a field.
+    FieldShared(Meta, Ident),
+    // #[heap_size(kind = "")] on a field.
+    FieldKind(Meta, Ident),
+}
+
+// Parses `#[heap_size(...)]` arguments like `Punctuated::parse_terminated`,
+// but also accepts the keyword `fn` as a name.
+fn parse_metas(input: ParseStream) -> Result<Punctuated<Meta, Token![,]>> {
+    let mut metas = Punctuated::new();
+    while !input.is_empty() {
+        let meta = if input.peek(Token![fn]) {
+            let fn_token: Token![fn] = input.parse()?;
+            let path = syn::Path::from(Ident::new(HEAP_ATTR_FN_IDENT, fn_token.span));
+            if input.peek(Token![=]) {
+                Meta::NameValue(MetaNameValue {
+                    path,
+                    eq_token: input.parse()?,
+                    value: input.parse()?,
+                })
+            } else {
+                Meta::Path(path)
+            }
+        } else {
+            input.parse()?
+        };
+        metas.push_value(meta);
+        if input.is_empty() {
+            break;
+        }
+        metas.push_punct(input.parse()?);
+    }
+    Ok(metas)
 }
 
 impl HeapAttr {
@@ -59,18 +259,24 @@ impl HeapAttr {
         is_field: bool,
         is_variant: bool,
         origin: T,
-    ) -> Result<Option<Self>> {
+    ) -> Result<(Option<Self>, HeapOptions)> {
         let mut attrs = vec![];
+        let mut options = HeapOptions::default();
         for attr in raw_attrs {
             match &attr.meta {
                 Meta::List(meta_list) => {
                     if meta_list.path.is_ident(HEAP_IDENT) {
-                        let heap_attrs = meta_list
-                            .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
-                        if heap_attrs.len() > 1 {
+                        let heap_attrs = meta_list.parse_args_with(parse_metas)?;
+                        let mut count = 0;
+                        for meta in heap_attrs {
+                            if !options.parse(&meta, is_field, is_variant)? {
+                                count += 1;
+                                attrs.push(meta);
+                            }
+                        }
+                        if count > 1 {
                             bail!(meta_list, "too many heap_size attributes");
                         }
-                        attrs.extend(heap_attrs);
                     }
                 }
                 Meta::Path(path) => {
@@ -82,38 +288,19 @@ impl HeapAttr {
             }
         }
         let meta = if attrs.is_empty() {
-            return Ok(None);
+            return Ok((None, options));
         } else if attrs.len() == 1 {
             attrs.pop().unwrap()
         } else {
             bail!(origin, "too many heap_size attributes")
         };
+        let attr = Self::from_meta(meta, is_field, is_variant)?;
+        Ok((attr, options))
+    }
 
+    fn from_meta(meta: Meta, is_field: bool, is_variant: bool) -> Result<Option<Self>> {
         match meta {
-            Meta::Path(ref name) => {
-                if name.is_ident(HEAP_IDENT) {
-                    if is_field {
-                        Ok(Some(HeapAttr::Field))
-                    } else {
-                        Ok(Some(HeapAttr::Container(meta)))
-                    }
-                } else if name.is_ident(HEAP_ATTR_SKIP_IDENT) {
-                    if is_field || is_variant {
-                        Ok(Some(HeapAttr::FieldSkip(meta)))
-                    } else {
-                        bail!(meta, "`#[heap_size(skip)]` is a field attribute")
-                    }
-                } else if name.is_ident(HEAP_ATTR_WITH_IDENT) {
-                    bail!(
-                        meta,
-                        "heap_size attribute `with` must be followed by \
-                        a module path, `with = \"some::mod\"`"
-                    )
-                } else {
-                    let name = name.to_token_stream().to_string().replace(' ', "");
-                    bail!(meta, "unknown heap_size attribute `{}`", name)
-                }
-            }
+            Meta::Path(ref name) => Self::from_path(&meta, name, is_field, is_variant),
             Meta::NameValue(MetaNameValue {
                 ref path,
                 value:
@@ -122,30 +309,469 @@ impl HeapAttr {
                         ..
                     }),
                 ..
-            }) => {
-                if path.is_ident(HEAP_ATTR_WITH_IDENT) {
-                    Ok(Some(HeapAttr::FieldWith(meta.clone(), mod_path.clone())))
-                } else {
-                    let name = path.to_token_stream().to_string().replace(' ', "");
-                    bail!(meta, "unknown heap_size attribute `{}`", name)
-                }
-            }
+            }) => Self::from_name_value(&meta, path, mod_path, is_field, is_variant),
             meta => {
                 let full = meta.to_token_stream().to_string();
                 bail!(meta, "unknown heap attribute `{}`", full)
             }
         }
     }
+
+    // #[heap_size(name)]
+    fn from_path(
+        meta: &Meta,
+        name: &syn::Path,
+        is_field: bool,
+        is_variant: bool,
+    ) -> Result<Option<Self>> {
+        if name.is_ident(HEAP_IDENT) {
+            if is_field {
+                Ok(Some(HeapAttr::Field))
+            } else {
+                Ok(Some(HeapAttr::Container(meta.clone())))
+            }
+        } else if name.is_ident(HEAP_ATTR_SKIP_IDENT) {
+            if is_field || is_variant {
+                Ok(Some(HeapAttr::FieldSkip(meta.clone())))
+            } else {
+                bail!(meta, "`#[heap_size(skip)]` is a field attribute")
+            }
+        } else if name.is_ident(HEAP_ATTR_ZERO_IDENT) {
+            if is_field {
+                Ok(Some(HeapAttr::FieldZero))
+            } else {
+                bail!(meta, "`#[heap_size(zero)]` is a field attribute")
+            }
+        } else if name.is_ident(HEAP_ATTR_EXHAUSTIVE_IDENT) {
+            if is_field || is_variant {
+                bail!(meta, "`#[heap_size(exhaustive)]` is a container attribute");
+            }
+            Ok(Some(HeapAttr::Exhaustive))
+        } else if name.is_ident(HEAP_ATTR_AUTO_IDENT) {
+            if is_field || is_variant {
+                bail!(meta, "`#[heap_size(auto)]` is a container attribute");
+            }
+            Ok(Some(HeapAttr::Auto))
+        } else if name.is_ident(HEAP_ATTR_PAR_IDENT) {
+            if is_field {
+                Ok(Some(HeapAttr::FieldPar))
+            } else {
+                bail!(meta, "`#[heap_size(par)]` is a field attribute")
+            }
+        } else if name.is_ident(HEAP_ATTR_SHALLOW_IDENT) {
+            if is_field || is_variant {
+                Ok(Some(HeapAttr::FieldShallow))
+            } else {
+                bail!(
+                    meta,
+                    "`#[heap_size(shallow)]` is a field or variant attribute"
+                )
+            }
+        } else if let Some((ident, hint)) = HEAP_ATTR_VALUE_HINTS
+            .iter()
+            .find(|(ident, _)| name.is_ident(ident))
+        {
+            bail!(
+                meta,
+                "heap_size attribute `{}` must be followed by {}",
+                ident,
+                hint
+            )
+        } else {
+            let name = name.to_token_stream().to_string().replace(' ', "");
+            bail!(meta, "unknown heap_size attribute `{}`", name)
+        }
+    }
+
+    // #[heap_size(name = "value")]
+    fn from_name_value(
+        meta: &Meta,
+        path: &syn::Path,
+        mod_path: &LitStr,
+        is_field: bool,
+        is_variant: bool,
+    ) -> Result<Option<Self>> {
+        if path.is_ident(HEAP_ATTR_WITH_IDENT) {
+            if is_field {
+                Ok(Some(HeapAttr::FieldWith(meta.clone(), mod_path.clone())))
+            } else {
+                let path = mod_path.parse::<syn::Path>()?;
+                let path = parse_quote_spanned!(mod_path.span()=> #path::heap_size);
+                Ok(Some(HeapAttr::ContainerWith(meta.clone(), path)))
+            }
+        } else if path.is_ident(HEAP_ATTR_FN_IDENT) {
+            let path = mod_path.parse::<syn::Path>()?;
+            if is_field {
+                Ok(Some(HeapAttr::FieldFn(path)))
+            } else {
+                Ok(Some(HeapAttr::ContainerWith(meta.clone(), path)))
+            }
+        } else if path.is_ident(HEAP_ATTR_EXPR_IDENT) {
+            if !is_field {
+                bail!(meta, "`#[heap_size(expr = \"...\")]` is a field attribute");
+            }
+            let expr = mod_path.parse::<Expr>()?;
+            Ok(Some(HeapAttr::FieldExpr(mod_path.clone(), expr)))
+        } else if path.is_ident(HEAP_ATTR_ACTIVE_IDENT) {
+            if is_field || is_variant {
+                bail!(
+                    meta,
+                    "`#[heap_size(active = \"...\")]` is a union attribute"
+                );
+            }
+            let method = mod_path.parse::<Ident>()?;
+            Ok(Some(HeapAttr::UnionActive(meta.clone(), method)))
+        } else if path.is_ident(HEAP_ATTR_WITH_CTX_IDENT) {
+            if !is_field {
+                bail!(
+                    meta,
+                    "`#[heap_size(with_ctx = \"...\")]` is a field attribute"
+                );
+            }
+            Ok(Some(HeapAttr::FieldWithCtx(meta.clone(), mod_path.clone())))
+        } else if path.is_ident(HEAP_ATTR_SHARED_IDENT) {
+            if !is_field {
+                bail!(
+                    meta,
+                    "`#[heap_size(shared = \"...\")]` is a field attribute"
+                );
+            }
+            let mode = match mod_path.value().as_str() {
+                "full" => "Full",
+                "proportional" => "Proportional",
+                mode => bail!(
+                    mod_path,
+                    "unknown shared mode `{}`, expected `full` or `proportional`",
+                    mode
+                ),
+            };
+            let mode = Ident::new(mode, mod_path.span());
+            Ok(Some(HeapAttr::FieldShared(meta.clone(), mode)))
+        } else if path.is_ident(HEAP_ATTR_KIND_IDENT) {
+            if !is_field {
+                bail!(meta, "`#[heap_size(kind = \"...\")]` is a field attribute");
+            }
+            let kind = match mod_path.value().as_str() {
+                "heap" => "Heap",
+                "mmap" => "Mmap",
+                "shared" => "Shared",
+                "static" => "Static",
+                "pinned" => "Pinned",
+                kind => bail!(
+                    mod_path,
+                    "unknown memory kind `{}`, expected `heap`, `mmap`, \
+                    `shared`, `static` or `pinned`",
+                    kind
+                ),
+            };
+            let kind = Ident::new(kind, mod_path.span());
+            Ok(Some(HeapAttr::FieldKind(meta.clone(), kind)))
+        } else {
+            let name = path.to_token_stream().to_string().replace(' ', "");
+            bail!(meta, "unknown heap_size attribute `{}`", name)
+        }
+    }
+}
+
+// Attributes that can be combined with any other attribute.
+#[derive(Default)]
+struct HeapOptions {
+    // #[heap_size(bound = "")] on a container or a field.
+    bound: Option<Vec<WherePredicate>>,
+    // #[heap_size(crate = "")] on a container.
+    krate: Option<syn::Path>,
+    // #[heap_size(remote = "")] on a container.
+    remote: Option<syn::Path>,
+    // #[heap_size(add = ...)] on a field.
+    add: Option<Expr>,
+}
+
+impl HeapOptions {
+    // Returns true if `meta` is an option.
+    fn parse(&mut self, meta: &Meta, is_field: bool, is_variant: bool) -> Result<bool> {
+        let Meta::NameValue(MetaNameValue { path, value, .. }) = meta else {
+            return Ok(false);
+        };
+        if path.is_ident(HEAP_ATTR_ADD_IDENT) {
+            if !is_field {
+                bail!(meta, "`#[heap_size(add = ...)]` is a field attribute");
+            }
+            if self.add.is_some() {
+                bail!(meta, "duplicate heap_size attribute `add`");
+            }
+            // Either a constant expression or a string of it.
+            self.add = Some(match value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(lit), ..
+                }) => lit.parse()?,
+                value => value.clone(),
+            });
+            return Ok(true);
+        }
+        let name = if path.is_ident(HEAP_ATTR_BOUND_IDENT) {
+            if is_variant {
+                bail!(
+                    meta,
+                    "`#[heap_size(bound = \"...\")]` is a container or field attribute"
+                );
+            }
+            HEAP_ATTR_BOUND_IDENT
+        } else if path.is_ident(HEAP_ATTR_CRATE_IDENT) {
+            if is_field || is_variant {
+                bail!(
+                    meta,
+                    "`#[heap_size(crate = \"...\")]` is a container attribute"
+                );
+            }
+            HEAP_ATTR_CRATE_IDENT
+        } else if path.is_ident(HEAP_ATTR_REMOTE_IDENT) {
+            if is_field || is_variant {
+                bail!(
+                    meta,
+                    "`#[heap_size(remote = \"...\")]` is a container attribute"
+                );
+            }
+            HEAP_ATTR_REMOTE_IDENT
+        } else {
+            return Ok(false);
+        };
+        let Expr::Lit(ExprLit {
+            lit: Lit::Str(lit), ..
+        }) = value
+        else {
+            bail!(value, "heap_size attribute `{}` must be a string", name)
+        };
+        if name == HEAP_ATTR_BOUND_IDENT {
+            if self.bound.is_some() {
+                bail!(meta, "duplicate heap_size attribute `bound`");
+            }
+            let predicates =
+                lit.parse_with(Punctuated::<WherePredicate, Token![,]>::parse_terminated)?;
+            self.bound = Some(predicates.into_iter().collect());
+        } else if name == HEAP_ATTR_CRATE_IDENT {
+            if self.krate.is_some() {
+                bail!(meta, "duplicate heap_size attribute `crate`");
+            }
+            self.krate = Some(lit.parse()?);
+        } else {
+            if self.remote.is_some() {
+                bail!(meta, "duplicate heap_size attribute `remote`");
+            }
+            self.remote = Some(lit.parse()?);
+        }
+        Ok(true)
+    }
+
+    // Returns the path of the heapsz crate, `::heapsz` by default, which is
+    // spanned to `span` so that errors point at fields.
+    fn krate(&self, span: Span) -> syn::Path {
+        self.krate
+            .clone()
+            .unwrap_or_else(|| parse_quote_spanned!(span=> ::heapsz))
+    }
+}
+
+// Where predicates of generated impls.
+//
+// Type parameters that appear in counted fields, or their associated types,
+// e.g., `T::Assoc`, are bounded by `HeapSize`, unless bounds are given by
+// `#[heap_size(bound = "...")]`.
+struct Bounds {
+    krate: syn::Path,
+    params: Vec<Ident>,
+    container: Option<Vec<WherePredicate>>,
+    inferred: Vec<syn::Type>,
+    fields: Vec<WherePredicate>,
+}
+
+impl Bounds {
+    fn new(generics: &Generics, container: &HeapOptions) -> Self {
+        Bounds {
+            krate: container.krate(Span::call_site()),
+            params: generics.type_params().map(|p| p.ident.clone()).collect(),
+            container: container.bound.clone(),
+            inferred: vec![],
+            fields: vec![],
+        }
+    }
+
+    fn add_field(&mut self, field: &HeapField) {
+        if let Some(bound) = &field.bound {
+            self.fields.extend(bound.iter().cloned());
+            return;
+        }
+        match field.attr {
+            HeapAttr::Field
+            | HeapAttr::FieldPar
+            | HeapAttr::FieldShared(..)
+            | HeapAttr::FieldKind(..) => {}
+            // Other attributes call user functions or do not require
+            // `HeapSize`.
+            _ => return,
+        }
+        let mut finder = FindParams {
+            params: &self.params,
+            found: &mut self.inferred,
+        };
+        finder.visit_type(&field.field.ty);
+    }
+
+    // Returns generics with where predicates, `par` adds `Sync` to inferred
+    // bounds for `ParHeapSize`.
+    fn generics(&self, generics: &Generics, par: bool) -> Generics {
+        let mut generics = generics.clone();
+        let where_clause = generics.make_where_clause();
+        if let Some(container) = &self.container {
+            where_clause.predicates.extend(container.iter().cloned());
+            return generics;
+        }
+        let krate = &self.krate;
+        for ty in &self.inferred {
+            where_clause.predicates.push(if par {
+                parse_quote!(#ty: #krate::HeapSize + ::core::marker::Sync)
+            } else {
+                parse_quote!(#ty: #krate::HeapSize)
+            });
+        }
+        where_clause.predicates.extend(self.fields.iter().cloned());
+        generics
+    }
+}
+
+// Finds type parameters in a type, except those in `PhantomData`. An
+// associated type of a parameter, e.g., `T::Assoc` or `<T as Trait>::Assoc`,
+// is found as a whole, like serde does, because `T` itself may not be counted.
+struct FindParams<'a> {
+    params: &'a [Ident],
+    found: &'a mut Vec<syn::Type>,
+}
+
+impl FindParams<'_> {
+    // Whether the path starts with a type parameter, e.g., `T` or `T::Assoc`.
+    fn starts_with_param(&self, path: &syn::Path) -> bool {
+        match (path.leading_colon, path.segments.first()) {
+            (None, Some(first)) => self.params.contains(&first.ident),
+            _ => false,
+        }
+    }
+
+    fn add(&mut self, ty: syn::Type) {
+        let key = ty.to_token_stream().to_string();
+        if !self
+            .found
+            .iter()
+            .any(|found| found.to_token_stream().to_string() == key)
+        {
+            self.found.push(ty);
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for FindParams<'_> {
+    fn visit_type_path(&mut self, ty: &'ast syn::TypePath) {
+        let projection = match &ty.qself {
+            Some(qself) => match &*qself.ty {
+                syn::Type::Path(self_ty) => {
+                    self_ty.qself.is_none() && self.starts_with_param(&self_ty.path)
+                }
+                _ => false,
+            },
+            None => ty.path.segments.len() > 1 && self.starts_with_param(&ty.path),
+        };
+        if projection {
+            self.add(syn::Type::Path(ty.clone()));
+            return;
+        }
+        visit::visit_type_path(self, ty);
+    }
+
+    fn visit_path(&mut self, path: &'ast syn::Path) {
+        if path
+            .segments
+            .last()
+            .map_or(false, |s| s.ident == "PhantomData")
+        {
+            return;
+        }
+        if self.starts_with_param(path) {
+            let param = &path.segments[0].ident;
+            self.add(parse_quote!(#param));
+        }
+        visit::visit_path(self, path);
+    }
+}
+
+// Whether a field type owns heap memory, recognized by its name in
+// `#[heap_size(auto)]`.
+enum AutoKind<'a> {
+    Heap,
+    Pod,
+    // The innermost type that is not recognized.
+    Unknown(&'a syn::Type),
+}
+
+impl<'a> AutoKind<'a> {
+    fn of(ty: &'a syn::Type) -> Self {
+        match ty {
+            syn::Type::Paren(ty) => AutoKind::of(&ty.elem),
+            syn::Type::Group(ty) => AutoKind::of(&ty.elem),
+            // References and pointers do not own what they point to.
+            syn::Type::Reference(_) | syn::Type::Ptr(_) | syn::Type::BareFn(_) => AutoKind::Pod,
+            syn::Type::Array(array) => AutoKind::of(&array.elem),
+            syn::Type::Tuple(tuple) => AutoKind::all(tuple.elems.iter()),
+            syn::Type::Path(path) if path.qself.is_none() => {
+                let Some(last) = path.path.segments.last() else {
+                    return AutoKind::Unknown(ty);
+                };
+                let name = last.ident.to_string();
+                if AUTO_HEAP_TYPES.contains(&name.as_str()) {
+                    return AutoKind::Heap;
+                }
+                if AUTO_POD_TYPES.contains(&name.as_str()) {
+                    return AutoKind::Pod;
+                }
+                // `Option<T>` owns heap memory if `T` does.
+                if name == "Option" {
+                    if let syn::PathArguments::AngleBracketed(args) = &last.arguments {
+                        if let Some(syn::GenericArgument::Type(inner)) = args.args.first() {
+                            return AutoKind::of(inner);
+                        }
+                    }
+                }
+                AutoKind::Unknown(ty)
+            }
+            _ => AutoKind::Unknown(ty),
+        }
+    }
+
+    // A compound type owns heap memory if any part does, and it is unknown if
+    // any part is unknown.
+    fn all(types: impl Iterator<Item = &'a syn::Type>) -> Self {
+        let mut kind = AutoKind::Pod;
+        for ty in types {
+            match AutoKind::of(ty) {
+                AutoKind::Heap => kind = AutoKind::Heap,
+                AutoKind::Pod => (),
+                unknown @ AutoKind::Unknown(_) => return unknown,
+            }
+        }
+        kind
+    }
 }
 
 enum MethodReceiver {
     FieldIdent,
     Replace(Ident),
     PrefixRef(Ident),
+    // A field of a union, which is only read if it is active.
+    UnionRef(Ident),
 }
 
 struct HeapField {
+    krate: syn::Path,
     attr: HeapAttr,
+    bound: Option<Vec<WherePredicate>>,
+    add: Option<Expr>,
     ident: TokenStream,
     field: Field,
 }
@@ -156,10 +782,13 @@ impl HeapField {
         field: Field,
         container_attr: Option<&HeapAttr>,
         variant_attr: Option<&HeapAttr>,
+        krate: &syn::Path,
     ) -> Result<Option<Self>> {
-        let require_container_attr = |meta| {
-            if let Some(HeapAttr::Container(_)) = container_attr {
-                Ok(None)
+        let require_container_attr = |meta: &Meta| {
+            if let (Some(HeapAttr::Container(_) | HeapAttr::Exhaustive | HeapAttr::Auto), _)
+            | (_, Some(HeapAttr::Container(_))) = (container_attr, variant_attr)
+            {
+                Ok(HeapAttr::FieldSkip(meta.clone()))
             } else {
                 bail!(
                     meta,
@@ -168,19 +797,71 @@ impl HeapField {
                 );
             }
         };
-        let attr = match HeapAttr::new(&field.attrs, true, false, &field)? {
+        let (attr, options) = HeapAttr::new(&field.attrs, true, false, &field)?;
+        let attr = match attr {
             None => {
-                if let Some(HeapAttr::FieldSkip(meta)) = variant_attr {
-                    return require_container_attr(meta);
+                if let Some(HeapAttr::Container(_)) = variant_attr {
+                    HeapAttr::Field
+                } else if let Some(HeapAttr::FieldShallow) = variant_attr {
+                    HeapAttr::FieldShallow
                 } else if let Some(HeapAttr::Container(_)) = container_attr {
                     HeapAttr::Field
+                } else if options.add.is_some() {
+                    // The field only owns the constant number of bytes.
+                    HeapAttr::FieldZero
+                } else if let Some(HeapAttr::Exhaustive) = container_attr {
+                    // Every field must be classified, so that a new field can
+                    // not silently report 0.
+                    let name = field
+                        .ident
+                        .as_ref()
+                        .map_or_else(|| index.to_string(), Ident::to_string);
+                    bail!(
+                        field,
+                        "field `{}` is not classified, add `#[heap_size]`, \
+                        `#[heap_size(skip)]`, `#[heap_size(zero)]` or \
+                        `#[heap_size(with = \"...\")]` in `#[heap_size(exhaustive)]`",
+                        name
+                    );
+                } else if let Some(HeapAttr::Auto) = container_attr {
+                    match AutoKind::of(&field.ty) {
+                        AutoKind::Heap => HeapAttr::Field,
+                        AutoKind::Pod => HeapAttr::FieldZero,
+                        AutoKind::Unknown(ty) => {
+                            let name = field
+                                .ident
+                                .as_ref()
+                                .map_or_else(|| index.to_string(), Ident::to_string);
+                            let name_ty = ty.to_token_stream().to_string().replace(' ', "");
+                            bail!(
+                                ty,
+                                "can not tell whether `{}` of field `{}` owns heap memory \
+                                in `#[heap_size(auto)]`, add `#[heap_size]`, \
+                                `#[heap_size(skip)]`, `#[heap_size(zero)]` or \
+                                `#[heap_size(with = \"...\")]`",
+                                name_ty,
+                                name
+                            );
+                        }
+                    }
                 } else {
-                    return Ok(None);
+                    HeapAttr::FieldZero
                 }
             }
-            Some(HeapAttr::FieldSkip(meta)) => return require_container_attr(&meta),
+            Some(HeapAttr::FieldSkip(meta)) => require_container_attr(&meta)?,
             Some(attr) => attr,
         };
+        // A borrow owns nothing, whatever it points to.
+        let attr = match (attr, &field.ty) {
+            (HeapAttr::Field | HeapAttr::FieldShallow, syn::Type::Reference(_)) => {
+                HeapAttr::FieldZero
+            }
+            (attr, _) => attr,
+        };
+        if let (HeapAttr::FieldZero, None) = (&attr, &options.add) {
+            // The field owns no heap memory.
+            return Ok(None);
+        }
 
         let ident = field.ident.clone().map_or_else(
             || {
@@ -193,12 +874,19 @@ impl HeapField {
             |x| quote!(#x),
         );
 
-        Ok(Some(HeapField { attr, ident, field }))
+        Ok(Some(HeapField {
+            krate: krate.clone(),
+            attr,
+            bound: options.bound,
+            add: options.add,
+            ident,
+            field,
+        }))
     }
 
-    fn method_heap_size(&self, self_: &MethodReceiver) -> Result<TokenStream> {
+    fn receiver(&self, self_: &MethodReceiver) -> TokenStream {
         let field_ident = &self.ident;
-        let ident = match self_ {
+        match self_ {
             MethodReceiver::FieldIdent => {
                 quote_spanned!(self.field.span()=> #field_ident)
             }
@@ -206,10 +894,75 @@ impl HeapField {
             MethodReceiver::PrefixRef(ident) => {
                 quote_spanned!(self.field.span()=> &#ident.#field_ident)
             }
+            MethodReceiver::UnionRef(ident) => {
+                quote_spanned!(self.field.span()=> unsafe { &#ident.#field_ident })
+            }
+        }
+    }
+
+    // A remote definition generates no `ParHeapSize` implementation.
+    fn check_remote(&self) -> Result<()> {
+        match self.attr {
+            HeapAttr::FieldPar => bail!(
+                self.field.clone(),
+                "`#[heap_size(par)]` is not supported with `#[heap_size(remote = \"...\")]`"
+            ),
+            // There is no `self` in the generated function.
+            HeapAttr::FieldExpr(ref lit, _) => bail!(
+                lit,
+                "`#[heap_size(expr = \"...\")]` is not supported with \
+                `#[heap_size(remote = \"...\")]`, use `fn` instead"
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    // Returns `par_heap_size()` of a `#[heap_size(par)]` field.
+    fn method_par_heap_size(&self, self_: &MethodReceiver) -> Option<TokenStream> {
+        let HeapAttr::FieldPar = self.attr else {
+            return None;
         };
+        let ident = self.receiver(self_);
+        let krate = &self.krate;
+        Some(quote_spanned! {self.field.span()=>
+            #krate::ParHeapSize::par_heap_size(#ident)
+        })
+    }
+
+    // Returns `heap_size()` of the field, which is named in retained and drift
+    // reports.
+    fn method_heap_size(&self, self_: &MethodReceiver) -> Result<TokenStream> {
+        let krate = &self.krate;
+        // A constant number of bytes, which must be a `usize`.
+        let add = self.add.as_ref().map(|add| {
+            quote_spanned! {add.span()=>
+                + { const ADD: usize = #add; ADD }
+            }
+        });
+        if let HeapAttr::FieldSkip(_) = self.attr {
+            // The heap size of a skipped field is unknown, it widens the
+            // bounds of an estimate.
+            return Ok(quote_spanned! {self.field.span()=>
+                #krate::estimate::unknown() #add
+            });
+        }
+        let heap_size = self.method_field_heap_size(self_)?;
+        let name = self.name();
+        Ok(quote_spanned! {self.field.span()=>
+            #krate::field_heap_size(#name, || #heap_size #add)
+        })
+    }
+
+    fn name(&self) -> String {
+        self.ident.to_string()
+    }
+
+    fn method_field_heap_size(&self, self_: &MethodReceiver) -> Result<TokenStream> {
+        let ident = self.receiver(self_);
+        let krate = &self.krate;
         match self.attr {
-            HeapAttr::Field => Ok(quote_spanned! {self.field.span()=>
-                ::heapsz::HeapSize::heap_size(#ident)
+            HeapAttr::Field | HeapAttr::FieldPar => Ok(quote_spanned! {self.field.span()=>
+                #krate::HeapSize::heap_size(#ident)
             }),
             HeapAttr::FieldWith(ref meta, ref mod_path) => {
                 let path = syn::parse_str::<syn::Path>(&mod_path.value())?;
@@ -217,12 +970,55 @@ impl HeapField {
                     #path::heap_size(#ident)
                 })
             }
+            HeapAttr::FieldFn(ref path) => Ok(quote_spanned! {path.span()=>
+                #path(#ident)
+            }),
+            // The expression must be a `usize`, it may refer to `self`.
+            HeapAttr::FieldExpr(ref lit, ref expr) => Ok(quote_spanned! {lit.span()=>
+                {
+                    let heap_size: usize = #expr;
+                    heap_size
+                }
+            }),
+            HeapAttr::FieldZero => Ok(quote_spanned! {self.field.span()=>
+                0
+            }),
+            HeapAttr::FieldShallow => Ok(quote_spanned! {self.field.span()=>
+                #krate::ShallowHeapSize::shallow_heap_size(#ident)
+            }),
+            HeapAttr::FieldShared(ref meta, ref mode) => Ok(quote_spanned! {meta.span()=>
+                #krate::traverse::with_shared(
+                    #krate::traverse::Shared::#mode,
+                    || #krate::HeapSize::heap_size(#ident),
+                )
+            }),
+            HeapAttr::FieldKind(ref meta, ref kind) => Ok(quote_spanned! {meta.span()=>
+                #krate::kind::override_kind_heap_size(
+                    #krate::kind::MemoryKind::#kind,
+                    || #krate::HeapSize::heap_size(#ident),
+                )
+            }),
+            HeapAttr::FieldWithCtx(ref meta, ref ctx_type) => {
+                let ctx_type = ctx_type.parse::<syn::Type>()?;
+                Ok(quote_spanned! {meta.span()=>
+                    #krate::context::heap_size_in_context::<#ctx_type, _>(#ident)
+                })
+            }
             HeapAttr::FieldSkip(_) => {
                 bail!(
                     self.field.clone(),
                     "internal error `#[heap_size(skip)]` field generates `fn heap_size()`",
                 );
             }
+            HeapAttr::Exhaustive
+            | HeapAttr::Auto
+            | HeapAttr::ContainerWith(..)
+            | HeapAttr::UnionActive(..) => {
+                bail!(
+                    self.field.clone(),
+                    "internal error unexpected container attribute is found on field",
+                );
+            }
             HeapAttr::Container(ref meta) => {
                 bail!(
                     self.field.clone(),
@@ -235,95 +1031,259 @@ impl HeapField {
 }
 
 fn render_struct(input: DeriveInput) -> Result<proc_macro2::TokenStream> {
-    let container_attrs = HeapAttr::new(&input.attrs, false, false, &input)?;
+    let (container_attrs, container_options) = HeapAttr::new(&input.attrs, false, false, &input)?;
+    let mut bounds = Bounds::new(&input.generics, &container_options);
+    let krate = container_options.krate(Span::call_site());
+    let remote = Remote::new(&container_options, &input.generics);
+    match container_attrs {
+        Some(HeapAttr::ContainerWith(ref meta, ref path)) => {
+            return render_container_with(&input, meta, path, &container_options);
+        }
+        Some(HeapAttr::UnionActive(ref meta, _)) => {
+            bail!(
+                meta,
+                "`#[heap_size(active = \"...\")]` is a union attribute"
+            );
+        }
+        _ => (),
+    }
 
     let ident = input.ident.clone();
     let Data::Struct(data) = input.data else {
         bail!(input, "{} should be a struct", ident);
     };
-    let fields = match data {
-        DataStruct {
-            fields:
-                Fields::Named(FieldsNamed { named: fields, .. })
-                | Fields::Unnamed(FieldsUnnamed {
-                    unnamed: fields, ..
-                }),
-            ..
-        } => fields.into_iter().collect(),
-        DataStruct {
-            fields: Fields::Unit,
-            ..
-        } => Vec::new(),
+    // A remote definition destructures the remote value, so that fields are
+    // checked against the remote type.
+    let (pattern, self_receivers, fields) = match &remote {
+        Some(remote) => render_pattern(
+            remote.path.to_token_stream(),
+            remote.path.span(),
+            data.fields,
+        ),
+        None => {
+            let fields = data.fields.into_iter().collect::<Vec<_>>();
+            let self_receivers = fields
+                .iter()
+                .map(|_| MethodReceiver::PrefixRef(Ident::new("self", Span::call_site())))
+                .collect();
+            (quote!(), self_receivers, fields)
+        }
     };
 
     let mut heap_sizes = vec![];
-    let self_ = MethodReceiver::PrefixRef(Ident::new("self", Span::call_site()));
+    let mut par_heap_sizes = vec![];
+    let mut rest_heap_sizes = vec![];
+    let mut type_checks = vec![];
     for (i, field) in fields.into_iter().enumerate() {
-        if let Some(f) = HeapField::new(i, field.clone(), container_attrs.as_ref(), None)? {
-            heap_sizes.push(f.method_heap_size(&self_)?);
+        if remote.is_some() {
+            type_checks.push(render_type_check(&field, &self_receivers[i]));
+        }
+        if let Some(f) = HeapField::new(
+            i,
+            field.clone(),
+            container_attrs.as_ref(),
+            None,
+            &container_options.krate(field.span()),
+        )? {
+            if remote.is_some() {
+                f.check_remote()?;
+            }
+            bounds.add_field(&f);
+            let heap_size = f.method_heap_size(&self_receivers[i])?;
+            match f.method_par_heap_size(&self_receivers[i]) {
+                Some(par_heap_size) => par_heap_sizes.push(par_heap_size),
+                None => rest_heap_sizes.push(heap_size.clone()),
+            }
+            heap_sizes.push(heap_size);
         }
     }
 
-    let generics = &input.generics;
+    let generics = bounds.generics(&input.generics, false);
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    if let Some(Remote { ty, .. }) = &remote {
+        let vis = &input.vis;
+        return Ok(quote! {
+            impl #impl_generics #ident #ty_generics #where_clause {
+                #vis fn heap_size(value: &#ty) -> usize {
+                    #krate::drift::checked_heap_size::<#ty>(|| {
+                        let #pattern = value;
+                        #(#type_checks)*
+                        0 #(+ #heap_sizes)*
+                    })
+                }
+            }
+        });
+    }
+    let par_impl = if par_heap_sizes.is_empty() {
+        quote!()
+    } else {
+        let par_sum = render_par_sum(&par_heap_sizes, &rest_heap_sizes, &krate);
+        let par_generics = bounds.generics(&input.generics, true);
+        let (impl_generics, _, where_clause) = par_generics.split_for_impl();
+        quote! {
+            impl #impl_generics #krate::ParHeapSize for #ident #ty_generics #where_clause {
+                fn par_heap_size(&self) -> usize {
+                    #par_sum
+                }
+            }
+        }
+    };
     Ok(quote! {
-        impl #impl_generics ::heapsz::HeapSize for #ident #ty_generics #where_clause {
+        impl #impl_generics #krate::HeapSize for #ident #ty_generics #where_clause {
             fn heap_size(&self) -> usize {
-                0 #(+ #heap_sizes)*
+                #krate::drift::checked_heap_size::<Self>(|| 0 #(+ #heap_sizes)*)
             }
         }
+        #par_impl
     })
 }
 
-fn render_enum(input: DeriveInput) -> Result<TokenStream> {
-    let container_attrs = HeapAttr::new(&input.attrs, false, false, &input)?;
-
-    let ident = input.ident.clone();
-    let Data::Enum(data) = input.data else {
-        bail!(input, "{} should be an enum", ident);
-    };
-    let mut rendered_vars = vec![];
-    for var in data.variants {
-        rendered_vars.push(render_enum_variant(var, container_attrs.as_ref())?);
+// Delegates the whole implementation to a function, by a container
+// attribute `#[heap_size(with = "...")]` or `#[heap_size(fn = "...")]`.
+fn render_container_with(
+    input: &DeriveInput,
+    meta: &Meta,
+    path: &syn::Path,
+    container_options: &HeapOptions,
+) -> Result<TokenStream> {
+    if let Some(remote) = &container_options.remote {
+        bail!(
+            remote,
+            "`#[heap_size(remote = \"...\")]` can not be combined with a container \
+            `with` or `fn`, use the function directly"
+        );
     }
-    let matches = if rendered_vars.is_empty() {
-        quote!(0)
-    } else {
-        quote! {
-            #[allow(unused_variables)]
-            match self {
-                #(#rendered_vars)*
+    let krate = container_options.krate(Span::call_site());
+    let ident = &input.ident;
+    let generics = Bounds::new(&input.generics, container_options).generics(&input.generics, false);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let heap_size = quote_spanned! {meta.span()=>
+        #path(self)
+    };
+    Ok(quote! {
+        impl #impl_generics #krate::HeapSize for #ident #ty_generics #where_clause {
+            fn heap_size(&self) -> usize {
+                #krate::drift::checked_heap_size::<Self>(|| #heap_size)
             }
         }
+    })
+}
+
+fn render_union(input: DeriveInput) -> Result<TokenStream> {
+    let (container_attrs, container_options) = HeapAttr::new(&input.attrs, false, false, &input)?;
+    let (meta, method) = match container_attrs {
+        Some(HeapAttr::ContainerWith(ref meta, ref path)) => {
+            return render_container_with(&input, meta, path, &container_options);
+        }
+        Some(HeapAttr::UnionActive(meta, method)) => (meta, method),
+        _ => bail!(
+            input,
+            "`HeapSize` can not be derived for a union without a container attribute \
+            `#[heap_size(with = \"...\")]`, `#[heap_size(fn = \"...\")]` or \
+            `#[heap_size(active = \"...\")]`"
+        ),
     };
+    if let Some(remote) = &container_options.remote {
+        bail!(
+            remote,
+            "`#[heap_size(remote = \"...\")]` is not supported for a union"
+        );
+    }
+    let mut bounds = Bounds::new(&input.generics, &container_options);
+    let krate = container_options.krate(Span::call_site());
 
-    let generics = &input.generics;
+    let ident = input.ident.clone();
+    let Data::Union(data) = input.data else {
+        bail!(input, "{} should be a union", ident);
+    };
+    let container_attr = HeapAttr::UnionActive(meta.clone(), method.clone());
+    let self_ = MethodReceiver::UnionRef(Ident::new("self", Span::call_site()));
+    let mut arms = vec![];
+    for (i, field) in data.fields.named.into_iter().enumerate() {
+        if let Some(f) = HeapField::new(
+            i,
+            field.clone(),
+            Some(&container_attr),
+            None,
+            &container_options.krate(field.span()),
+        )? {
+            bounds.add_field(&f);
+            let name = f.name();
+            let heap_size = f.method_heap_size(&self_)?;
+            arms.push(quote! {
+                #name => #heap_size,
+            });
+        }
+    }
+
+    let generics = bounds.generics(&input.generics, false);
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    // Only the active field is read, other names own no heap memory. Reading
+    // it is only sound if the method is right, so it must be an `unsafe fn`
+    // that vouches for it. A safe method makes the `unsafe` block unused.
+    let active = quote_spanned! {meta.span()=>
+        #[deny(unused_unsafe)]
+        let active: &str = unsafe { Self::#method(self) };
+    };
     Ok(quote! {
-        impl #impl_generics ::heapsz::HeapSize for #ident #ty_generics #where_clause {
+        impl #impl_generics #krate::HeapSize for #ident #ty_generics #where_clause {
             fn heap_size(&self) -> usize {
-                #matches
+                #krate::drift::checked_heap_size::<Self>(|| {
+                    #active
+                    match active {
+                        #(#arms)*
+                        _ => 0,
+                    }
+                })
             }
         }
     })
 }
 
-fn render_enum_variant(var: Variant, container_attr: Option<&HeapAttr>) -> Result<TokenStream> {
-    let var_attrs = HeapAttr::new(&var.attrs, false, true, &var)?;
-    let var_span = var.span();
-    let var_ident = var.ident;
-    let (match_arm, self_receivers, fields) = match var.fields {
+// The type of `#[heap_size(remote = "...")]`.
+struct Remote {
+    // The path in patterns, without generic arguments.
+    path: syn::Path,
+    // The type of the remote value.
+    ty: TokenStream,
+}
+
+impl Remote {
+    fn new(options: &HeapOptions, generics: &Generics) -> Option<Self> {
+        let remote = options.remote.as_ref()?;
+        let mut path = remote.clone();
+        let last = path.segments.last_mut()?;
+        // Generic arguments are the same as the definition's if omitted.
+        let ty = if last.arguments.is_empty() {
+            let (_, ty_generics, _) = generics.split_for_impl();
+            quote!(#remote #ty_generics)
+        } else {
+            quote!(#remote)
+        };
+        last.arguments = syn::PathArguments::None;
+        Some(Remote { path, ty })
+    }
+}
+
+// Returns a pattern that destructures `path` with `fields`, receivers of the
+// bindings, and the fields.
+fn render_pattern(
+    path: TokenStream,
+    span: Span,
+    fields: Fields,
+) -> (TokenStream, Vec<MethodReceiver>, Vec<Field>) {
+    match fields {
         Fields::Named(FieldsNamed { named: fields, .. }) => {
             let idents = fields.iter().map(|f| f.ident.clone().unwrap());
-            let match_arm = quote_spanned! {var_span=>
-                Self::#var_ident { #(#idents,)* }
+            let pattern = quote_spanned! {span=>
+                #path { #(#idents,)* }
             };
             let self_receivers = fields
                 .iter()
                 .map(|_| MethodReceiver::FieldIdent)
                 .collect::<Vec<_>>();
             (
-                match_arm,
+                pattern,
                 self_receivers,
                 fields.into_iter().collect::<Vec<_>>(),
             )
@@ -340,31 +1300,263 @@ fn render_enum_variant(var: Variant, container_attr: Option<&HeapAttr>) -> Resul
                 .iter()
                 .map(|ident| MethodReceiver::Replace(ident.clone()))
                 .collect::<Vec<_>>();
-            let match_arm = quote_spanned! {var_span=>
-                Self::#var_ident(#(#field_idents,)*)
+            let pattern = quote_spanned! {span=>
+                #path(#(#field_idents,)*)
             };
             (
-                match_arm,
+                pattern,
                 self_receivers,
                 fields.into_iter().collect::<Vec<_>>(),
             )
         }
-        Fields::Unit => {
-            let match_arm = quote_spanned! {var_span=>
-                Self::#var_ident
-            };
-            (match_arm, vec![], vec![])
+        Fields::Unit => (path, vec![], vec![]),
+    }
+}
+
+// Returns the binding of a field in a pattern.
+fn render_binding(field: &Field, self_: &MethodReceiver) -> TokenStream {
+    match self_ {
+        MethodReceiver::FieldIdent => field.ident.to_token_stream(),
+        MethodReceiver::Replace(ident)
+        | MethodReceiver::PrefixRef(ident)
+        | MethodReceiver::UnionRef(ident) => ident.to_token_stream(),
+    }
+}
+
+// Checks the type of a field of a remote definition against the remote type.
+fn render_type_check(field: &Field, self_: &MethodReceiver) -> TokenStream {
+    let ty = &field.ty;
+    let binding = render_binding(field, self_);
+    quote_spanned! {ty.span()=>
+        let _: &#ty = #binding;
+    }
+}
+
+// Sum up `#[heap_size(par)]` fields and the rest fields, every par field is
+// traversed concurrently by `rayon::join`.
+fn render_par_sum(
+    par_heap_sizes: &[TokenStream],
+    rest_heap_sizes: &[TokenStream],
+    krate: &syn::Path,
+) -> TokenStream {
+    let mut sum = quote!(0 #(+ #rest_heap_sizes)*);
+    for par_heap_size in par_heap_sizes.iter().rev() {
+        sum = quote! {{
+            let (par, rest) = #krate::par::join(|| #par_heap_size, || #sum);
+            par + rest
+        }};
+    }
+    sum
+}
+
+fn render_enum(input: DeriveInput) -> Result<TokenStream> {
+    let (container_attrs, container_options) = HeapAttr::new(&input.attrs, false, false, &input)?;
+    let mut bounds = Bounds::new(&input.generics, &container_options);
+    let krate = container_options.krate(Span::call_site());
+    let remote = Remote::new(&container_options, &input.generics);
+    match container_attrs {
+        Some(HeapAttr::ContainerWith(ref meta, ref path)) => {
+            return render_container_with(&input, meta, path, &container_options);
+        }
+        Some(HeapAttr::UnionActive(ref meta, _)) => {
+            bail!(
+                meta,
+                "`#[heap_size(active = \"...\")]` is a union attribute"
+            );
+        }
+        _ => (),
+    }
+
+    let ident = input.ident.clone();
+    let Data::Enum(data) = input.data else {
+        bail!(input, "{} should be an enum", ident);
+    };
+    let mut rendered_vars = vec![];
+    let mut rendered_par_vars = vec![];
+    for var in data.variants {
+        let (rendered_var, rendered_par_var) = render_enum_variant(
+            var,
+            container_attrs.as_ref(),
+            &mut bounds,
+            &container_options,
+            remote.as_ref(),
+        )?;
+        rendered_vars.push(rendered_var);
+        rendered_par_vars.push(rendered_par_var);
+    }
+    let par_matches = if rendered_par_vars.iter().any(Option::is_some) {
+        // Variants without `#[heap_size(par)]` fields are the same as
+        // `heap_size()`.
+        let rendered_par_vars = rendered_par_vars
+            .into_iter()
+            .zip(&rendered_vars)
+            .map(|(par_var, var)| par_var.unwrap_or_else(|| var.clone()));
+        Some(quote! {
+            #[allow(unused_variables)]
+            match self {
+                #(#rendered_par_vars)*
+            }
+        })
+    } else {
+        None
+    };
+
+    let generics = bounds.generics(&input.generics, false);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    if let Some(Remote { path, ty }) = &remote {
+        let vis = &input.vis;
+        // Missing variants are reported at the remote path.
+        let matches = quote_spanned! {path.span()=>
+            match value {
+                #(#rendered_vars)*
+            }
+        };
+        return Ok(quote! {
+            impl #impl_generics #ident #ty_generics #where_clause {
+                #vis fn heap_size(value: &#ty) -> usize {
+                    #krate::drift::checked_heap_size::<#ty>(|| #matches)
+                }
+            }
+        });
+    }
+    let matches = if rendered_vars.is_empty() {
+        quote!(0)
+    } else {
+        quote! {
+            #[allow(unused_variables)]
+            match self {
+                #(#rendered_vars)*
+            }
         }
     };
+    let par_impl = par_matches.map(|par_matches| {
+        let par_generics = bounds.generics(&input.generics, true);
+        let (impl_generics, _, where_clause) = par_generics.split_for_impl();
+        quote! {
+            impl #impl_generics #krate::ParHeapSize for #ident #ty_generics #where_clause {
+                fn par_heap_size(&self) -> usize {
+                    #par_matches
+                }
+            }
+        }
+    });
+    Ok(quote! {
+        impl #impl_generics #krate::HeapSize for #ident #ty_generics #where_clause {
+            fn heap_size(&self) -> usize {
+                #krate::drift::checked_heap_size::<Self>(|| { #matches })
+            }
+        }
+        #par_impl
+    })
+}
+
+// Returns match arms of `heap_size()` and `par_heap_size()`, the latter is
+// `None` if the variant has no `#[heap_size(par)]` field.
+fn render_enum_variant(
+    var: Variant,
+    container_attr: Option<&HeapAttr>,
+    bounds: &mut Bounds,
+    container_options: &HeapOptions,
+    remote: Option<&Remote>,
+) -> Result<(TokenStream, Option<TokenStream>)> {
+    let (var_attrs, _) = HeapAttr::new(&var.attrs, false, true, &var)?;
+    let var_span = var.span();
+    let var_ident = var.ident;
+    let path = if let Some(remote) = remote {
+        let path = &remote.path;
+        quote_spanned!(var_span=> #path::#var_ident)
+    } else {
+        quote_spanned!(var_span=> Self::#var_ident)
+    };
+    let (match_arm, self_receivers, fields) = render_pattern(path, var_span, var.fields);
+    let krate = container_options.krate(Span::call_site());
+    // Fields are reported under the variant name.
+    let name = var_ident.to_string();
+    let type_checks: Vec<_> = if remote.is_some() {
+        fields
+            .iter()
+            .zip(&self_receivers)
+            .map(|(field, self_)| render_type_check(field, self_))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    if let Some(HeapAttr::FieldSkip(ref meta)) = var_attrs {
+        // A skipped variant owns nothing, whatever its fields say.
+        if !matches!(
+            container_attr,
+            Some(HeapAttr::Container(_) | HeapAttr::Exhaustive | HeapAttr::Auto)
+        ) {
+            bail!(
+                meta,
+                "`#[heap_size(skip)]` is only allow with a container \
+                attribute `#[heap_size]`."
+            );
+        }
+        return Ok((quote!(#match_arm => { #(#type_checks)* 0 }), None));
+    }
+    if let Some(HeapAttr::ContainerWith(ref meta, ref path)) = var_attrs {
+        // The function takes all fields of the variant.
+        let bindings = fields
+            .iter()
+            .zip(&self_receivers)
+            .map(|(field, self_)| render_binding(field, self_));
+        let heap_size = quote_spanned! {meta.span()=>
+            #path(#(#bindings),*)
+        };
+        return Ok((
+            quote! {
+                #match_arm => {
+                    #(#type_checks)*
+                    #krate::field_heap_size(#name, || #heap_size)
+                }
+            },
+            None,
+        ));
+    }
 
     let mut heap_sizes = vec![];
+    let mut par_heap_sizes = vec![];
+    let mut rest_heap_sizes = vec![];
     for (i, field) in fields.into_iter().enumerate() {
-        if let Some(f) = HeapField::new(i, field.clone(), container_attr, var_attrs.as_ref())? {
-            heap_sizes.push(f.method_heap_size(&self_receivers[i])?);
+        if let Some(f) = HeapField::new(
+            i,
+            field.clone(),
+            container_attr,
+            var_attrs.as_ref(),
+            &container_options.krate(field.span()),
+        )? {
+            if remote.is_some() {
+                f.check_remote()?;
+            }
+            bounds.add_field(&f);
+            let heap_size = f.method_heap_size(&self_receivers[i])?;
+            match f.method_par_heap_size(&self_receivers[i]) {
+                Some(par_heap_size) => par_heap_sizes.push(par_heap_size),
+                None => rest_heap_sizes.push(heap_size.clone()),
+            }
+            heap_sizes.push(heap_size);
         }
     }
 
-    Ok(quote_spanned! {var_span=>
-        #match_arm => { 0 #(+ #heap_sizes)* }
-    })
+    let par_arm = if par_heap_sizes.is_empty() {
+        None
+    } else {
+        let par_sum = render_par_sum(&par_heap_sizes, &rest_heap_sizes, &krate);
+        Some(quote! {
+            #match_arm => #par_sum
+        })
+    };
+    let heap_size = if heap_sizes.is_empty() {
+        quote!(0)
+    } else {
+        quote!(#krate::field_heap_size(#name, || 0 #(+ #heap_sizes)*))
+    };
+    Ok((
+        quote! {
+            #match_arm => { #(#type_checks)* #heap_size }
+        },
+        par_arm,
+    ))
 }
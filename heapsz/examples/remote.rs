@@ -0,0 +1,36 @@
+pub mod remote {
+    pub struct Inner {
+        pub data: Vec<u8>,
+        pub id: u64,
+    }
+
+    pub enum Value {
+        Bytes(Vec<u8>),
+        Int(i64),
+    }
+}
+
+#[derive(heapsz_derive::HeapSize)]
+#[heap_size(remote = "remote::Inner")]
+pub struct InnerDef {
+    #[heap_size]
+    pub data: Vec<u8>,
+    pub id: u64,
+}
+
+#[derive(heapsz_derive::HeapSize)]
+#[heap_size(remote = "remote::Value")]
+pub enum ValueDef {
+    Bytes(#[heap_size] Vec<u8>),
+    Int(i64),
+}
+
+#[derive(heapsz_derive::HeapSize)]
+pub struct Outer {
+    #[heap_size(with = "InnerDef")]
+    pub inner: remote::Inner,
+    #[heap_size(with = "ValueDef")]
+    pub value: remote::Value,
+}
+
+fn main() {}
@@ -0,0 +1,22 @@
+pub mod remote {
+    pub struct Inner(pub Box<u8>);
+}
+
+pub fn inner_heap_size(t: &remote::Inner, len: &usize) -> usize {
+    std::mem::size_of_val(&*t.0) * len
+}
+
+#[derive(heapsz_derive::HeapSize)]
+pub enum Variants {
+    #[heap_size]
+    All(Vec<u8>, Box<u64>),
+    #[heap_size(shallow)]
+    Shallow {
+        items: Vec<String>,
+    },
+    #[heap_size(fn = "inner_heap_size")]
+    Fn(remote::Inner, usize),
+    None(Vec<u8>),
+}
+
+fn main() {}
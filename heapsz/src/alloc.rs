@@ -0,0 +1,305 @@
+//! Attribute allocations to named scopes.
+//!
+//! Estimation can not see allocations hidden in opaque types. Installed as
+//! the global allocator, [`ScopedAllocator`] attributes live bytes to the
+//! innermost [`scope`] of the allocating thread. Every allocation remembers
+//! its scope, so it is credited back correctly even if it is freed on
+//! another thread.
+//!
+//! ```
+//! use std::alloc::System;
+//!
+//! use heapsz::alloc::ScopedAllocator;
+//!
+//! #[global_allocator]
+//! static ALLOC: ScopedAllocator<System> = ScopedAllocator::new(System);
+//!
+//! let _g = heapsz::scope("compaction");
+//! let buf = vec![0u8; 4096];
+//! drop(_g);
+//! assert!(ALLOC.report().roots[0].retained >= 4096);
+//! # drop(buf);
+//! ```
+
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    cell::Cell,
+    marker::PhantomData,
+    mem::{align_of, size_of},
+    sync::{
+        atomic::{AtomicIsize, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+use crate::retained::{Entry, Report};
+
+/// The maximum number of named scopes, allocations in scopes registered
+/// beyond it are not attributed.
+pub const MAX_SCOPES: usize = 255;
+
+// Names of registered scopes, the id of a scope is its index plus one, and
+// 0 means no scope.
+static NAMES: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static CURRENT: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A guard of a [`scope`], which restores the outer scope once dropped.
+///
+/// The scope belongs to the thread that entered it, so the guard is not
+/// `Send`.
+#[derive(Debug)]
+#[must_use = "the scope ends once the guard is dropped"]
+pub struct ScopeGuard {
+    prev: usize,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|c| c.set(self.prev));
+    }
+}
+
+/// Attribute allocations made by the current thread to the scope `name`,
+/// until the returned guard is dropped.
+///
+/// Scopes nest, allocations are attributed to the innermost one.
+pub fn scope(name: &'static str) -> ScopeGuard {
+    let id = {
+        let mut names = NAMES.lock().unwrap_or_else(PoisonError::into_inner);
+        match names.iter().position(|n| *n == name) {
+            Some(i) => i + 1,
+            None if names.len() < MAX_SCOPES => {
+                names.push(name);
+                names.len()
+            }
+            None => 0,
+        }
+    };
+    ScopeGuard {
+        prev: CURRENT.with(|c| c.replace(id)),
+        _not_send: PhantomData,
+    }
+}
+
+fn current() -> usize {
+    // The thread local may be destroyed while the thread exits.
+    CURRENT.try_with(Cell::get).unwrap_or(0)
+}
+
+/// A global allocator which attributes live bytes to named scopes.
+///
+/// Every allocation is prefixed by a header that stores its scope.
+#[derive(Debug)]
+pub struct ScopedAllocator<A> {
+    inner: A,
+    live: [AtomicIsize; MAX_SCOPES + 1],
+}
+
+impl<A> ScopedAllocator<A> {
+    /// Create an allocator that allocates by `inner`.
+    pub const fn new(inner: A) -> Self {
+        ScopedAllocator {
+            inner,
+            live: [const { AtomicIsize::new(0) }; MAX_SCOPES + 1],
+        }
+    }
+
+    /// Return live bytes of every scope, in the order they are registered,
+    /// in the same format as [`retained`](crate::retained) reports.
+    pub fn report(&self) -> Report {
+        let names = NAMES.lock().unwrap_or_else(PoisonError::into_inner);
+        let roots = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Entry {
+                path: (*name).to_owned(),
+                retained: usize::try_from(self.live[i + 1].load(Ordering::Relaxed)).unwrap_or(0),
+            })
+            .collect();
+        Report {
+            roots,
+            fields: vec![],
+        }
+    }
+
+    fn credit(&self, id: usize, bytes: isize) {
+        if id != 0 {
+            self.live[id].fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+}
+
+// The header is large enough for a scope id and keeps the alignment.
+fn header(layout: Layout) -> usize {
+    layout.align().max(size_of::<usize>())
+}
+
+// Allocations never exceed `isize::MAX` bytes.
+fn signed(bytes: usize) -> isize {
+    isize::try_from(bytes).unwrap_or(isize::MAX)
+}
+
+// Return the scope id right before the value.
+#[allow(clippy::cast_ptr_alignment)]
+unsafe fn scope_id(ptr: *mut u8) -> *mut usize {
+    // SAFETY: the header is at least a `usize` and the outer allocation is
+    // aligned for it, see `with_header`.
+    ptr.cast::<usize>().sub(1)
+}
+
+// The outer allocation is aligned for both the value and the scope id, which
+// is read and written right before the value.
+fn with_header(layout: Layout) -> Option<Layout> {
+    let size = layout.size().checked_add(header(layout))?;
+    Layout::from_size_align(size, layout.align().max(align_of::<usize>())).ok()
+}
+
+// SAFETY: allocations are delegated to `inner`, which is a `GlobalAlloc`,
+// and the header is stripped before returning.
+unsafe impl<A: GlobalAlloc> GlobalAlloc for ScopedAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(outer) = with_header(layout) else {
+            return std::ptr::null_mut();
+        };
+        let raw = self.inner.alloc(outer);
+        if raw.is_null() {
+            return raw;
+        }
+        let ptr = raw.add(header(layout));
+        let id = current();
+        scope_id(ptr).write(id);
+        self.credit(id, signed(layout.size()));
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let Some(outer) = with_header(layout) else {
+            return std::ptr::null_mut();
+        };
+        let raw = self.inner.alloc_zeroed(outer);
+        if raw.is_null() {
+            return raw;
+        }
+        let ptr = raw.add(header(layout));
+        let id = current();
+        scope_id(ptr).write(id);
+        self.credit(id, signed(layout.size()));
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Credit the scope that allocated it, whichever thread frees it.
+        let id = scope_id(ptr).read();
+        self.credit(id, -signed(layout.size()));
+        let outer = with_header(layout).unwrap();
+        self.inner.dealloc(ptr.sub(header(layout)), outer);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let header = header(layout);
+        let Some(new_outer_size) = new_size.checked_add(header) else {
+            return std::ptr::null_mut();
+        };
+        let id = scope_id(ptr).read();
+        let outer = with_header(layout).unwrap();
+        let raw = self.inner.realloc(ptr.sub(header), outer, new_outer_size);
+        if raw.is_null() {
+            return raw;
+        }
+        // The allocation stays in the scope that allocated it.
+        self.credit(id, signed(new_size) - signed(layout.size()));
+        raw.add(header)
+    }
+}
+
+#[test]
+fn test_scoped_allocator() {
+    use std::alloc::System;
+
+    static ALLOC: ScopedAllocator<System> = ScopedAllocator::new(System);
+    let live = |name: &str| {
+        ALLOC
+            .report()
+            .roots
+            .into_iter()
+            .find(|e| e.path == name)
+            .map_or(0, |e| e.retained)
+    };
+
+    unsafe {
+        let layout = Layout::from_size_align(100, 64).unwrap();
+        let g = scope("test_scoped_allocator");
+        let ptr = ALLOC.alloc(layout);
+        assert_eq!(0, ptr as usize % 64);
+        let inner = scope("test_scoped_allocator_inner");
+        let zeroed = ALLOC.alloc_zeroed(Layout::new::<[u8; 8]>());
+        assert_eq!([0u8; 8], *(zeroed as *const [u8; 8]));
+        drop(inner);
+        drop(g);
+        let unscoped = ALLOC.alloc(Layout::new::<u64>());
+        assert_eq!(100, live("test_scoped_allocator"));
+        assert_eq!(8, live("test_scoped_allocator_inner"));
+
+        let ptr = ALLOC.realloc(ptr, layout, 200);
+        assert_eq!(200, live("test_scoped_allocator"));
+
+        // Freed on another thread.
+        let ptr = ptr as usize;
+        std::thread::spawn(move || {
+            let _g = scope("test_scoped_allocator_other");
+            let layout = Layout::from_size_align(200, 64).unwrap();
+            ALLOC.dealloc(ptr as *mut u8, layout);
+        })
+        .join()
+        .unwrap();
+        assert_eq!(0, live("test_scoped_allocator"));
+        assert_eq!(0, live("test_scoped_allocator_other"));
+
+        ALLOC.dealloc(zeroed, Layout::new::<[u8; 8]>());
+        ALLOC.dealloc(unscoped, Layout::new::<u64>());
+        assert_eq!(0, live("test_scoped_allocator_inner"));
+    }
+}
+
+#[test]
+fn test_scoped_allocator_align() {
+    use std::alloc::System;
+
+    // An allocator which returns odd addresses whenever it is allowed to.
+    struct Odd;
+    unsafe impl GlobalAlloc for Odd {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if layout.align() != 1 {
+                return System.alloc(layout);
+            }
+            let raw = System.alloc(Layout::from_size_align(layout.size() + 1, 2).unwrap());
+            if raw.is_null() { raw } else { raw.add(1) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            if layout.align() != 1 {
+                return System.dealloc(ptr, layout);
+            }
+            System.dealloc(
+                ptr.sub(1),
+                Layout::from_size_align(layout.size() + 1, 2).unwrap(),
+            );
+        }
+    }
+
+    static ALLOC: ScopedAllocator<Odd> = ScopedAllocator::new(Odd);
+    unsafe {
+        let layout = Layout::from_size_align(3, 1).unwrap();
+        let _g = scope("test_scoped_allocator_align");
+        let ptr = ALLOC.alloc(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(0, ptr as usize % align_of::<usize>());
+        let ptr = ALLOC.realloc(ptr, layout, 5);
+        assert_eq!(0, ptr as usize % align_of::<usize>());
+        ALLOC.dealloc(ptr, Layout::from_size_align(5, 1).unwrap());
+    }
+}
@@ -0,0 +1,176 @@
+//! Size values against a shared context, e.g., an interner or an arena.
+//!
+//! Interned strings and arena-backed values are shared by many owners.
+//! Counting them per owner double counts, and counting them as zero hides the
+//! interner or the arena. Instead, a value asks a context whether its
+//! allocation has already been charged.
+//!
+//! ```
+//! use std::rc::Rc;
+//!
+//! use heapsz::context::{heap_size_in_context, with_context, Charged};
+//!
+//! let s: Rc<str> = Rc::from("interned");
+//! let names = vec![s.clone(), s.clone(), s];
+//!
+//! let charged = Charged::new();
+//! let bytes = with_context(&charged, || {
+//!     names
+//!         .iter()
+//!         .map(heap_size_in_context::<Charged, _>)
+//!         .sum::<usize>()
+//! });
+//! assert_eq!("interned".len(), bytes);
+//! ```
+
+use std::{any::TypeId, cell::RefCell, collections::HashSet, rc::Rc, sync::Arc};
+
+use crate::{modes::Installed, HeapSize};
+
+/// Query the number of bytes of an object against a context `C`.
+///
+/// It can be used by `#[derive(HeapSize)]` with
+/// `#[heap_size(with_ctx = "C")]`.
+pub trait HeapSizeWith<C: ?Sized> {
+    /// Return the number of bytes it owns on the heap, which have not been
+    /// charged to `cx` yet.
+    fn heap_size_with(&self, cx: &C) -> usize;
+}
+
+thread_local! {
+    // A stack of installed contexts.
+    static CONTEXTS: RefCell<Vec<(TypeId, *const ())>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Install `cx` as the context of type `C` on the current thread while
+/// running `f`.
+#[allow(clippy::module_name_repetitions)]
+pub fn with_context<C: 'static, R>(cx: &C, f: impl FnOnce() -> R) -> R {
+    // Uninstall the context even if `f` panics.
+    struct Pop;
+    impl Drop for Pop {
+        fn drop(&mut self) {
+            CONTEXTS.with(|cxs| cxs.borrow_mut().pop());
+        }
+    }
+    CONTEXTS.with(|cxs| {
+        cxs.borrow_mut()
+            .push((TypeId::of::<C>(), std::ptr::from_ref(cx).cast::<()>()));
+    });
+    let _pop = Pop;
+    let _installed = Installed::new();
+    f()
+}
+
+/// Call `f` with the innermost context of type `C` installed by
+/// [`with_context`], or `None` if there is no such context.
+#[allow(clippy::module_name_repetitions)]
+pub fn current_context<C: 'static, R>(f: impl FnOnce(Option<&C>) -> R) -> R {
+    let ptr = CONTEXTS.with(|cxs| {
+        cxs.borrow()
+            .iter()
+            .rev()
+            .find(|(id, _)| *id == TypeId::of::<C>())
+            .map(|(_, ptr)| ptr.cast::<C>())
+    });
+    // SAFETY: The context is installed by `with_context` which outlives this
+    // call, and the reference can not escape `f`.
+    f(ptr.map(|ptr| unsafe { &*ptr }))
+}
+
+/// Return the heap size of `value` against the innermost context of type
+/// `C`, or 0 if there is no such context.
+#[allow(clippy::module_name_repetitions)]
+pub fn heap_size_in_context<C: 'static, T: HeapSizeWith<C> + ?Sized>(value: &T) -> usize {
+    current_context(|cx: Option<&C>| cx.map_or(0, |cx| value.heap_size_with(cx)))
+}
+
+/// A context that remembers charged allocations, so that every allocation is
+/// charged only once.
+#[derive(Debug, Default)]
+pub struct Charged {
+    addrs: RefCell<HashSet<usize>>,
+}
+
+impl Charged {
+    /// Create an empty context.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return `bytes` if the allocation at `ptr` is charged for the first
+    /// time, otherwise 0.
+    pub fn charge<T: ?Sized>(&self, ptr: *const T, bytes: usize) -> usize {
+        if self.addrs.borrow_mut().insert(ptr.cast::<()>() as usize) {
+            bytes
+        } else {
+            0
+        }
+    }
+
+    /// Forget all charged allocations.
+    pub fn clear(&self) {
+        self.addrs.borrow_mut().clear();
+    }
+}
+
+impl<T: HeapSize> HeapSizeWith<Charged> for Rc<T> {
+    fn heap_size_with(&self, cx: &Charged) -> usize {
+        cx.charge(Rc::as_ptr(self), self.heap_size())
+    }
+}
+
+impl HeapSizeWith<Charged> for Rc<str> {
+    fn heap_size_with(&self, cx: &Charged) -> usize {
+        cx.charge(Rc::as_ptr(self), self.len())
+    }
+}
+
+impl<T: HeapSize> HeapSizeWith<Charged> for Arc<T> {
+    fn heap_size_with(&self, cx: &Charged) -> usize {
+        cx.charge(Arc::as_ptr(self), self.heap_size())
+    }
+}
+
+impl HeapSizeWith<Charged> for Arc<str> {
+    fn heap_size_with(&self, cx: &Charged) -> usize {
+        cx.charge(Arc::as_ptr(self), self.len())
+    }
+}
+
+#[test]
+fn test_charged() {
+    let charged = Charged::new();
+    let a = Arc::new(vec![0u8; 8]);
+    let b = Arc::new(vec![0u8; 8]);
+    assert_eq!(a.heap_size(), a.heap_size_with(&charged));
+    assert_eq!(0, a.clone().heap_size_with(&charged));
+    assert_eq!(b.heap_size(), b.heap_size_with(&charged));
+
+    charged.clear();
+    assert_eq!(a.heap_size(), a.heap_size_with(&charged));
+
+    let s: Arc<str> = Arc::from("abc");
+    assert_eq!(3, s.heap_size_with(&charged));
+    assert_eq!(0, s.heap_size_with(&charged));
+}
+
+#[test]
+fn test_with_context() {
+    let s: Rc<str> = Rc::from("abc");
+    assert_eq!(0, heap_size_in_context::<Charged, _>(&s));
+
+    let outer = Charged::new();
+    let inner = Charged::new();
+    with_context(&outer, || {
+        assert_eq!(3, heap_size_in_context::<Charged, _>(&s));
+        with_context(&inner, || {
+            // The innermost context is used.
+            assert_eq!(3, heap_size_in_context::<Charged, _>(&s));
+        });
+        assert_eq!(0, heap_size_in_context::<Charged, _>(&s));
+    });
+    assert_eq!(0, heap_size_in_context::<Charged, _>(&s));
+    current_context(|cx: Option<&Charged>| assert!(cx.is_none()));
+}
@@ -0,0 +1,363 @@
+//! Detect drift between O(1) estimates and exact heap sizes.
+//!
+//! [`HeapSize::heap_size`](crate::HeapSize::heap_size) approximates a
+//! collection by its first element, which lies if the first element is not
+//! representative. Once a [`Detector`] is installed, every Nth call of
+//! `heap_size()` on a `#[derive(HeapSize)]` type also traverses the value
+//! exactly, and reports a [`Drift`] if the relative error exceeds a
+//! threshold.
+//!
+//! ```
+//! use std::sync::{Arc, Mutex};
+//!
+//! use heapsz::drift::{self, Detector};
+//!
+//! let drifts = Arc::new(Mutex::new(vec![]));
+//! let drifts1 = drifts.clone();
+//! Detector::new(move |d| drifts1.lock().unwrap().push(d.clone()))
+//!     .every(1)
+//!     .threshold(0.5)
+//!     .install();
+//! // ... call `heap_size()` in tests ...
+//! drift::uninstall();
+//! ```
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+};
+
+use crate::{
+    estimate::is_estimating,
+    modes::{any_installed, Installed},
+    traverse::{is_traversing, Traversal},
+};
+
+/// A field whose estimated heap size drifts from its exact heap size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Drift {
+    /// The type whose `heap_size()` is called.
+    pub type_name: &'static str,
+    /// The path of the field that drifts the most, e.g., `entries.key`, it
+    /// is empty if the drift can not be narrowed down to a field.
+    pub field_path: String,
+    /// The estimated number of bytes returned by `heap_size()`.
+    pub estimate: usize,
+    /// The exact number of bytes.
+    pub exact: usize,
+}
+
+impl Drift {
+    /// Return the relative error of the estimate.
+    #[must_use]
+    pub fn relative_error(&self) -> f64 {
+        relative_error(self.estimate, self.exact)
+    }
+}
+
+// A ratio does not need every bit of the sizes.
+#[allow(clippy::cast_precision_loss)]
+fn relative_error(estimate: usize, exact: usize) -> f64 {
+    estimate.abs_diff(exact) as f64 / exact.max(1) as f64
+}
+
+type Callback = Box<dyn Fn(&Drift) + Send + Sync>;
+
+/// A drift detector, which can be installed process-wide.
+pub struct Detector {
+    every: usize,
+    threshold: f64,
+    callback: Callback,
+    calls: AtomicUsize,
+}
+
+impl std::fmt::Debug for Detector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Detector")
+            .field("every", &self.every)
+            .field("threshold", &self.threshold)
+            .finish_non_exhaustive()
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static DETECTOR: RwLock<Option<Arc<Detector>>> = RwLock::new(None);
+
+impl Detector {
+    /// Create a detector which reports drifts to `callback`.
+    ///
+    /// By default, it checks every 100th call, and reports relative errors
+    /// greater than 0.1.
+    pub fn new(callback: impl Fn(&Drift) + Send + Sync + 'static) -> Self {
+        Detector {
+            every: 100,
+            threshold: 0.1,
+            callback: Box::new(callback),
+            calls: AtomicUsize::new(0),
+        }
+    }
+
+    /// Check every `every`th call of `heap_size()`.
+    #[must_use]
+    pub fn every(mut self, every: usize) -> Self {
+        self.every = every.max(1);
+        self
+    }
+
+    /// Report drifts whose relative error is greater than `threshold`.
+    #[must_use]
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Install the detector process-wide, replacing the previous one.
+    pub fn install(self) {
+        *DETECTOR.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(self));
+        ENABLED.store(true, Ordering::Release);
+    }
+}
+
+/// Uninstall the detector.
+pub fn uninstall() {
+    ENABLED.store(false, Ordering::Release);
+    DETECTOR
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+}
+
+#[derive(Debug, Default)]
+struct Recorder {
+    path: Vec<&'static str>,
+    // Elements of approximated collections are scaled up by their lengths.
+    scale: usize,
+    bytes: HashMap<String, usize>,
+}
+
+thread_local! {
+    // Whether a check is running, nested checks are skipped.
+    static CHECKING: Cell<bool> = const { Cell::new(false) };
+    static RECORDER: RefCell<Option<Recorder>> = const { RefCell::new(None) };
+}
+
+/// Return the heap size of a value of type `T`, where `f` returns its heap
+/// size, and check it against an exact traversal every Nth call.
+///
+/// It is called by `#[derive(HeapSize)]`.
+#[inline]
+pub fn checked_heap_size<T: ?Sized>(f: impl Fn() -> usize) -> usize {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return f();
+    }
+    check_heap_size::<T>(f)
+}
+
+#[inline(never)]
+fn check_heap_size<T: ?Sized>(f: impl Fn() -> usize) -> usize {
+    // Reset the flag even if `f` panics.
+    struct Reset;
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            CHECKING.with(|c| c.set(false));
+            RECORDER.with(|r| r.borrow_mut().take());
+        }
+    }
+    if CHECKING.with(Cell::get) || is_traversing() || is_estimating() {
+        return f();
+    }
+    let Some(detector) = DETECTOR
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+    else {
+        return f();
+    };
+    CHECKING.with(|c| c.set(true));
+    let _reset = Reset;
+    if detector.calls.fetch_add(1, Ordering::Relaxed) % detector.every != 0 {
+        return f();
+    }
+
+    let (estimate, estimates) = record(&f);
+    let (exact, exacts) = record(|| Traversal::new().run_with(&f).heap_size);
+    if relative_error(estimate, exact) > detector.threshold {
+        let field_path = drill_down(&estimates, &exacts, detector.threshold);
+        (detector.callback)(&Drift {
+            type_name: std::any::type_name::<T>(),
+            field_path,
+            estimate,
+            exact,
+        });
+    }
+    estimate
+}
+
+fn record(f: impl FnOnce() -> usize) -> (usize, HashMap<String, usize>) {
+    RECORDER.with(|r| {
+        *r.borrow_mut() = Some(Recorder {
+            scale: 1,
+            ..Default::default()
+        });
+    });
+    let installed = Installed::new();
+    let bytes = f();
+    drop(installed);
+    let recorder = RECORDER.with(|r| r.borrow_mut().take()).unwrap_or_default();
+    (bytes, recorder.bytes)
+}
+
+// Descend from the whole value to the child field that drifts the most, as
+// long as the child drifts more than `threshold`.
+fn drill_down(
+    estimates: &HashMap<String, usize>,
+    exacts: &HashMap<String, usize>,
+    threshold: f64,
+) -> String {
+    let mut current = String::new();
+    loop {
+        let prefix = if current.is_empty() {
+            String::new()
+        } else {
+            format!("{current}.")
+        };
+        let child = estimates
+            .keys()
+            .chain(exacts.keys())
+            .filter(|path| {
+                path.strip_prefix(&prefix)
+                    .map_or(false, |rest| !rest.is_empty() && !rest.contains('.'))
+            })
+            .map(|path| {
+                let estimate = estimates.get(path).copied().unwrap_or(0);
+                let exact = exacts.get(path).copied().unwrap_or(0);
+                (path, estimate, exact)
+            })
+            .filter(|(_, estimate, exact)| relative_error(*estimate, *exact) > threshold)
+            .max_by_key(|(path, estimate, exact)| (estimate.abs_diff(*exact), *path));
+        match child {
+            Some((path, ..)) => current.clone_from(path),
+            None => return current,
+        }
+    }
+}
+
+/// Return the heap size of a field named `name`, where `f` returns the heap
+/// size of the field.
+///
+/// While checking, the heap size is recorded by the field path.
+pub fn field_heap_size(name: &'static str, f: impl FnOnce() -> usize) -> usize {
+    if !any_installed() {
+        return f();
+    }
+    let recording = RECORDER.with(|r| {
+        let mut r = r.borrow_mut();
+        let recorder = r.as_mut()?;
+        recorder.path.push(name);
+        Some(())
+    });
+    if recording.is_none() {
+        return f();
+    }
+    let bytes = f();
+    RECORDER.with(|r| {
+        if let Some(recorder) = r.borrow_mut().as_mut() {
+            let path = recorder.path.join(".");
+            *recorder.bytes.entry(path).or_default() += bytes.saturating_mul(recorder.scale);
+            recorder.path.pop();
+        }
+    });
+    bytes
+}
+
+/// Run `f` which computes the heap size of one element on behalf of `len`
+/// elements, so that fields of the element are scaled up while checking.
+pub(crate) fn scaled<R>(len: usize, f: impl FnOnce() -> R) -> R {
+    if !any_installed() {
+        return f();
+    }
+    let prev = RECORDER.with(|r| {
+        let mut r = r.borrow_mut();
+        let recorder = r.as_mut()?;
+        let scale = recorder.scale.saturating_mul(len);
+        Some(std::mem::replace(&mut recorder.scale, scale))
+    });
+    let Some(prev) = prev else {
+        return f();
+    };
+    let res = f();
+    RECORDER.with(|r| {
+        if let Some(recorder) = r.borrow_mut().as_mut() {
+            recorder.scale = prev;
+        }
+    });
+    res
+}
+
+#[test]
+fn test_drift() {
+    use std::sync::Mutex;
+
+    use crate::{field_heap_size, HeapSize};
+
+    struct Entry {
+        key: Vec<u8>,
+        value: u64,
+    }
+    impl HeapSize for Entry {
+        fn heap_size(&self) -> usize {
+            checked_heap_size::<Self>(|| field_heap_size("key", || self.key.heap_size()))
+        }
+    }
+    struct Cache {
+        entries: Vec<Entry>,
+    }
+    impl HeapSize for Cache {
+        fn heap_size(&self) -> usize {
+            checked_heap_size::<Self>(|| field_heap_size("entries", || self.entries.heap_size()))
+        }
+    }
+
+    let drifts = Arc::new(Mutex::new(vec![]));
+    let drifts1 = drifts.clone();
+    Detector::new(move |d| drifts1.lock().unwrap().push(d.clone()))
+        .every(2)
+        .threshold(0.5)
+        .install();
+
+    // The first entry is not representative.
+    let mut entries: Vec<_> = (0..10)
+        .map(|i| Entry {
+            key: vec![0; 100],
+            value: i,
+        })
+        .collect();
+    entries[0].key = vec![];
+    let cache = Cache { entries };
+    let cap_bytes = cache.entries.capacity() * std::mem::size_of::<Entry>();
+
+    assert_eq!(cap_bytes, cache.heap_size());
+    // Checks every 2nd call.
+    assert_eq!(cap_bytes, cache.heap_size());
+    assert_eq!(cap_bytes, cache.heap_size());
+    uninstall();
+    assert_eq!(cap_bytes, cache.heap_size());
+
+    let drifts = drifts.lock().unwrap();
+    assert_eq!(2, drifts.len());
+    assert_eq!(
+        Drift {
+            type_name: std::any::type_name::<Cache>(),
+            field_path: "entries.key".to_owned(),
+            estimate: cap_bytes,
+            exact: cap_bytes + 900,
+        },
+        drifts[0]
+    );
+    assert!(drifts[0].relative_error() > 0.5);
+    assert_eq!(9, cache.entries[9].value);
+}
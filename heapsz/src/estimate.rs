@@ -0,0 +1,278 @@
+//! Bounds of heap size estimates.
+//!
+//! [`HeapSize::heap_size`](crate::HeapSize::heap_size) returns a single number,
+//! which hides how uncertain
+//! it is. [`HeapSize::heap_size_estimate`](crate::HeapSize::heap_size_estimate)
+//! instead samples a few elements of every collection, and returns an
+//! [`Estimate`] with bounds:
+//!
+//! * Capacity-only parts, e.g., a `Vec<u8>`, are exact.
+//! * Sampled parts are bounded by the smallest and the largest samples.
+//! * Parts that can not be read, e.g., a locked `Mutex` or a skipped field,
+//!   widen the upper bound to `usize::MAX`.
+//!
+//! ```
+//! use heapsz::HeapSize;
+//!
+//! let v: Vec<Vec<u8>> = (0..100).map(|i| vec![0; i % 10]).collect();
+//! let estimate = v.heap_size_estimate();
+//! assert!(!estimate.exact);
+//! assert!(estimate.lower <= estimate.value && estimate.value <= estimate.upper);
+//! ```
+
+use std::cell::Cell;
+
+use crate::modes::Installed;
+#[cfg(test)]
+use crate::HeapSize;
+
+/// The maximum number of elements sampled in a collection.
+pub const SAMPLES: usize = 8;
+
+/// A heap size estimate with bounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Estimate {
+    /// The estimated number of bytes.
+    pub value: usize,
+    /// The lower bound of the number of bytes.
+    pub lower: usize,
+    /// The upper bound of the number of bytes, `usize::MAX` if it is
+    /// unbounded.
+    pub upper: usize,
+    /// Whether every part is exact, `value`, `lower` and `upper` are the same
+    /// if it is.
+    pub exact: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Slack {
+    // The distance from the value to the lower bound.
+    lower: usize,
+    // The distance from the value to the upper bound.
+    upper: usize,
+    inexact: bool,
+}
+
+impl Slack {
+    fn add(&mut self, other: Slack) {
+        self.lower = self.lower.saturating_add(other.lower);
+        self.upper = self.upper.saturating_add(other.upper);
+        self.inexact |= other.inexact;
+    }
+}
+
+thread_local! {
+    static SLACK: Cell<Option<Slack>> = const { Cell::new(None) };
+}
+
+/// Run `f` which returns a heap size, and return the heap size with bounds.
+///
+/// Collections sample up to [`SAMPLES`] elements while running `f`, so the
+/// value may differ from the first-element approximation of
+/// [`HeapSize::heap_size`](crate::HeapSize::heap_size).
+pub fn estimate(f: impl FnOnce() -> usize) -> Estimate {
+    // Add up to the outer estimate even if `f` panics.
+    struct Merge(Option<Slack>);
+    impl Drop for Merge {
+        fn drop(&mut self) {
+            let inner = SLACK.with(|s| s.replace(self.0)).unwrap_or_default();
+            update(|s| s.add(inner));
+        }
+    }
+    let outer = SLACK.with(|s| s.replace(Some(Slack::default())));
+    let _merge = Merge(outer);
+    let _installed = Installed::new();
+    let value = f();
+    let slack = SLACK.with(Cell::get).unwrap_or_default();
+    Estimate {
+        value,
+        lower: value.saturating_sub(slack.lower),
+        upper: value.saturating_add(slack.upper),
+        exact: !slack.inexact,
+    }
+}
+
+/// Whether an [`estimate`] is running on the current thread.
+pub fn is_estimating() -> bool {
+    SLACK.with(Cell::get).is_some()
+}
+
+fn update(f: impl FnOnce(&mut Slack)) {
+    SLACK.with(|s| {
+        if let Some(mut slack) = s.get() {
+            f(&mut slack);
+            s.set(Some(slack));
+        }
+    });
+}
+
+/// Widen the running estimate by `lower` bytes below and `upper` bytes above.
+pub fn widen(lower: usize, upper: usize) {
+    update(|s| {
+        s.add(Slack {
+            lower,
+            upper,
+            inexact: true,
+        });
+    });
+}
+
+/// Return 0 for a part whose heap size is unknown, e.g., a skipped field,
+/// and widen the upper bound of the running estimate to `usize::MAX`.
+#[must_use]
+pub fn unknown() -> usize {
+    widen(0, usize::MAX);
+    0
+}
+
+/// Heap sizes of elements sampled from a collection of `len` elements.
+pub(crate) struct Samples {
+    len: usize,
+    count: usize,
+    sum: usize,
+    min: usize,
+    max: usize,
+    // The slack outside the collection, `None` if it is not estimating.
+    outer: Option<Slack>,
+}
+
+impl Samples {
+    pub(crate) fn new(len: usize) -> Self {
+        let outer = SLACK.with(|s| {
+            let outer = s.get()?;
+            s.set(Some(Slack::default()));
+            Some(outer)
+        });
+        Samples {
+            len,
+            count: 0,
+            sum: 0,
+            min: usize::MAX,
+            max: 0,
+            outer,
+        }
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn push(&mut self, heap_size: usize) {
+        self.count += 1;
+        self.sum = self.sum.saturating_add(heap_size);
+        self.min = self.min.min(heap_size);
+        self.max = self.max.max(heap_size);
+    }
+
+    /// Return the sum of all elements, extrapolated from samples.
+    pub(crate) fn finish(self) -> usize {
+        // Extrapolate in `u128` which holds the product of two `usize`s, and
+        // saturate the result.
+        let saturate = |v: u128| usize::try_from(v).unwrap_or(usize::MAX);
+        let rest = self.len - self.count;
+        let (sum, wide_rest) = (self.sum as u128, rest as u128);
+        let value = match self.count {
+            0 => 0,
+            count => saturate(sum + sum * wide_rest / count as u128),
+        };
+        if let Some(mut outer) = self.outer {
+            // Unsampled elements are as uncertain as sampled ones.
+            let inner = SLACK.with(Cell::get).unwrap_or_default();
+            let scale = |slack: usize| slack.saturating_mul(self.len) / self.count.max(1);
+            let min = self.min.min(self.max);
+            let lower = saturate(sum + wide_rest * min as u128);
+            let upper = saturate(sum + wide_rest * self.max as u128);
+            outer.add(Slack {
+                lower: scale(inner.lower).saturating_add(value.saturating_sub(lower)),
+                upper: scale(inner.upper).saturating_add(upper.saturating_sub(value)),
+                inexact: inner.inexact || rest != 0,
+            });
+            SLACK.with(|s| s.set(Some(outer)));
+        }
+        value
+    }
+}
+
+#[test]
+fn test_estimate_exact() {
+    let v = vec![0u8; 100];
+    let estimate = v.heap_size_estimate();
+    assert_eq!(
+        Estimate {
+            value: 100,
+            lower: 100,
+            upper: 100,
+            exact: true,
+        },
+        estimate
+    );
+
+    let v: Vec<Vec<u8>> = (0..SAMPLES).map(|i| vec![0; i]).collect();
+    let estimate = v.heap_size_estimate();
+    assert!(estimate.exact);
+    assert_eq!(
+        crate::traverse::Traversal::new().run(&v).heap_size,
+        estimate.value
+    );
+}
+
+#[test]
+fn test_estimate_sampled() {
+    let v: Vec<Vec<u8>> = (0..100).map(|i| vec![0; i % 10]).collect();
+    let cap_bytes = v.capacity() * std::mem::size_of::<Vec<u8>>();
+    let estimate = v.heap_size_estimate();
+    assert!(!estimate.exact);
+    // Samples are 0..8, so the rest 92 elements are in [0, 7].
+    let sum = (0..SAMPLES).sum::<usize>();
+    assert_eq!(cap_bytes + sum, estimate.lower);
+    assert_eq!(cap_bytes + sum + 92 * 7, estimate.upper);
+    assert_eq!(cap_bytes + sum + sum * 92 / 8, estimate.value);
+
+    // Nested collections are scaled.
+    let vv = vec![v.clone(), v.clone()];
+    let nested = vv.heap_size_estimate();
+    assert!(nested.upper - nested.value >= 2 * (estimate.upper - estimate.value));
+}
+
+#[test]
+fn test_samples_saturate() {
+    let mut samples = Samples::new(1000);
+    samples.push(usize::MAX / 4);
+    samples.push(usize::MAX / 4);
+    assert_eq!(usize::MAX, samples.finish());
+
+    let mut samples = Samples::new(usize::MAX);
+    samples.push(usize::MAX);
+    samples.push(usize::MAX);
+    assert_eq!(usize::MAX, samples.finish());
+
+    let estimate = estimate(|| {
+        let mut samples = Samples::new(1000);
+        samples.push(usize::MAX / 4);
+        samples.push(1);
+        samples.finish()
+    });
+    assert_eq!(usize::MAX, estimate.value);
+    assert_eq!(usize::MAX, estimate.upper);
+    assert!(!estimate.exact);
+}
+
+#[test]
+fn test_estimate_unknown() {
+    let estimate = estimate(|| {
+        let mut heap_size = vec![0u8; 8].heap_size();
+        widen(1, 2);
+        heap_size += unknown();
+        heap_size
+    });
+    assert_eq!(
+        Estimate {
+            value: 8,
+            lower: 7,
+            upper: usize::MAX,
+            exact: false,
+        },
+        estimate
+    );
+    assert!(!is_estimating());
+}
@@ -36,6 +36,8 @@ decl_mod! {
     mod blank;
     mod blocklisting;
     mod enums;
+    mod remote;
     mod tuples;
+    mod variants;
     mod with;
 }
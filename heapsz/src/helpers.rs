@@ -0,0 +1,259 @@
+//! Helpers for implementing `HeapSize` on containers.
+//!
+//! They are the building blocks of the implementations in this crate, and
+//! they honor the active accuracy mode: an O(1) approximation by the first
+//! element by default, or an exact visit of every element in a
+//! [`Traversal`](crate::traverse::Traversal).
+
+use core::mem::size_of;
+
+use crate::{drift, elements_heap_size, measure, modes, resident, retained, HeapSize};
+
+/// Return the heap size of a contiguous buffer of `T`, e.g., `Vec<T>`.
+///
+/// It sums up the whole buffer, `cap` elements, and heap sizes of the `len`
+/// elements in use. The buffer is counted by `cap`, use
+/// [`contiguous_heap_size_at`] to count it by its resident pages or by the
+/// allocator like the built-in implementations.
+///
+/// ```
+/// use heapsz::{contiguous_heap_size, HeapSize};
+///
+/// pub struct SmallList<T> {
+///     buf: Vec<T>,
+/// }
+///
+/// impl<T: HeapSize> HeapSize for SmallList<T> {
+///     fn heap_size(&self) -> usize {
+///         contiguous_heap_size(self.buf.capacity(), self.buf.len(), &self.buf)
+///     }
+/// }
+///
+/// let list = SmallList {
+///     buf: vec![vec![0u8; 4]; 2],
+/// };
+/// assert_eq!(list.buf.heap_size(), list.heap_size());
+/// ```
+pub fn contiguous_heap_size<'a, T: HeapSize + 'a>(
+    cap: usize,
+    len: usize,
+    elements: impl IntoIterator<Item = &'a T>,
+) -> usize {
+    let cap_bytes = cap * size_of::<T>();
+    cap_bytes + elements_heap_size::<T>(len, elements.into_iter().map(T::heap_size))
+}
+
+/// Return the heap size of a contiguous buffer of `T` starting at `ptr`.
+///
+/// It is [`contiguous_heap_size`], except that the buffer honors
+/// [`measure`](crate::measure) and [`resident`](crate::resident) modes, like
+/// `Vec<T>`. `ptr` must be the start of the allocation of the buffer, because
+/// within [`measured`](crate::measure::measured) it is passed to the
+/// allocator.
+///
+/// ```
+/// use heapsz::{contiguous_heap_size_at, HeapSize};
+///
+/// pub struct SmallList<T> {
+///     buf: Vec<T>,
+/// }
+///
+/// impl<T: HeapSize> HeapSize for SmallList<T> {
+///     fn heap_size(&self) -> usize {
+///         let buf = &self.buf;
+///         contiguous_heap_size_at(buf.as_ptr(), buf.capacity(), buf.len(), buf)
+///     }
+/// }
+///
+/// let list = SmallList {
+///     buf: vec![0u8; 1 << 20],
+/// };
+/// assert_eq!(list.buf.resident_heap_size(), list.resident_heap_size());
+/// ```
+pub fn contiguous_heap_size_at<'a, T: HeapSize + 'a>(
+    ptr: *const T,
+    cap: usize,
+    len: usize,
+    elements: impl IntoIterator<Item = &'a T>,
+) -> usize {
+    let cap_bytes = buffer_bytes(ptr, cap * size_of::<T>());
+    cap_bytes + elements_heap_size::<T>(len, elements.into_iter().map(T::heap_size))
+}
+
+/// Return the heap size of a node-based collection of `T`, e.g.,
+/// `LinkedList<T>`.
+///
+/// Every one of the `len` elements is stored in a separate node on the heap,
+/// which takes `per_node` bytes in addition to the element. Nodes are counted
+/// by these sizes in every mode, because their allocations are not known.
+///
+/// ```
+/// use heapsz::{node_based_heap_size, HeapSize};
+///
+/// pub struct Chain<T> {
+///     nodes: std::collections::LinkedList<T>,
+/// }
+///
+/// impl<T: HeapSize> HeapSize for Chain<T> {
+///     fn heap_size(&self) -> usize {
+///         // A node has two pointers, `prev` and `next`.
+///         let per_node = 2 * std::mem::size_of::<usize>();
+///         node_based_heap_size(self.nodes.len(), per_node, &self.nodes)
+///     }
+/// }
+/// ```
+pub fn node_based_heap_size<'a, T: HeapSize + 'a>(
+    len: usize,
+    per_node: usize,
+    elements: impl IntoIterator<Item = &'a T>,
+) -> usize {
+    let node_bytes = len * (per_node + size_of::<T>());
+    node_bytes + elements_heap_size::<T>(len, elements.into_iter().map(T::heap_size))
+}
+
+/// Return the number of bytes of the elements of a slice, as if the slice
+/// owned them on the heap.
+///
+/// `HeapSize` for a borrow, e.g. `&[T]`, always returns 0, because a borrow
+/// does not own anything. However, a slice may be borrowed from an owned
+/// buffer, e.g., `Vec<T>` coerced to `&[T]`. This function treats the slice
+/// like a `Box<[T]>`.
+pub fn owned_slice_heap_size<T: HeapSize>(slice: &[T]) -> usize {
+    contiguous_heap_size(slice.len(), slice.len(), slice)
+}
+
+/// Return the heap size of a field named `name`, where `f` returns the heap
+/// size of the field.
+///
+/// `#[derive(HeapSize)]` calls it for every field, so that the field is named
+/// in [`retained`] reports and [`drift`] reports.
+///
+/// ```
+/// use heapsz::{field_heap_size, HeapSize};
+///
+/// pub struct Entry {
+///     key: Vec<u8>,
+/// }
+///
+/// impl HeapSize for Entry {
+///     fn heap_size(&self) -> usize {
+///         field_heap_size("key", || self.key.heap_size())
+///     }
+/// }
+/// ```
+#[inline]
+pub fn field_heap_size(name: &'static str, f: impl FnOnce() -> usize) -> usize {
+    if !modes::any_installed() {
+        return f();
+    }
+    recorded_field_heap_size(name, f)
+}
+
+#[inline(never)]
+fn recorded_field_heap_size(name: &'static str, f: impl FnOnce() -> usize) -> usize {
+    drift::field_heap_size(name, || retained::field_heap_size(name, f))
+}
+
+/// Return the number of bytes of a buffer of `bytes` bytes starting at `ptr`,
+/// honoring [`measure`](crate::measure) and [`resident`](crate::resident)
+/// modes.
+#[inline]
+pub(crate) fn buffer_bytes<T: ?Sized>(ptr: *const T, bytes: usize) -> usize {
+    if !modes::any_installed() {
+        return bytes;
+    }
+    moded_buffer_bytes(ptr.cast(), bytes)
+}
+
+// Out of line, so that the fast path of `buffer_bytes` can be inlined.
+#[inline(never)]
+fn moded_buffer_bytes(ptr: *const u8, bytes: usize) -> usize {
+    resident::resident_bytes(ptr, measure::measured_bytes(ptr, bytes))
+}
+
+/// Implement `HeapSize` and `ShallowHeapSize` for types that never own any
+/// heap memory.
+///
+/// ```
+/// #[derive(Default)]
+/// pub struct Id(u64);
+///
+/// heapsz::impl_zero!(Id);
+///
+/// use heapsz::HeapSize;
+/// assert_eq!(0, Id::default().heap_size());
+/// ```
+#[macro_export]
+macro_rules! impl_zero {
+    ( $($typ: ty),+ $(,)? ) => {
+        $(
+            impl $crate::HeapSize for $typ {
+                fn heap_size(&self) -> usize { 0 }
+            }
+            impl $crate::ShallowHeapSize for $typ {
+                fn shallow_heap_size(&self) -> usize { 0 }
+            }
+        )+
+    };
+}
+
+/// Implement `HeapSize` and `ShallowHeapSize` for containers of exactly one
+/// inner value.
+///
+/// Every container takes its generic parameters in brackets, then its type,
+/// and the type of the inner value after `=>`. The block borrows the inner
+/// value from `self`, and the container owns as many bytes as the inner value
+/// does. The implementations require the inner type to implement the traits.
+///
+/// ```
+/// pub struct Guarded<T>(std::cell::UnsafeCell<T>);
+/// pub struct Keyed<K, V>(Box<(K, V)>);
+///
+/// heapsz::impl_container! {
+///     self,
+///     /// Return the number of bytes it owns in heap.
+///     [T] Guarded<T> => T {
+///         // SAFETY: The example never mutates the value.
+///         unsafe { &*self.0.get() }
+///     },
+///     [K, V] Keyed<K, V> => Box<(K, V)> { &self.0 },
+/// }
+///
+/// use heapsz::HeapSize;
+/// let g = Guarded(std::cell::UnsafeCell::new(vec![0u8; 8]));
+/// assert_eq!(8, g.heap_size());
+/// let k = Keyed(Box::new((1u64, vec![0u8; 8])));
+/// assert_eq!(k.0.heap_size(), k.heap_size());
+/// ```
+#[macro_export]
+macro_rules! impl_container {
+    (
+        $self_: tt,
+        $(
+            $(#[$attr:meta])*
+            [$($gen: tt)*] $typ: ty => $inner: ty $heap_size_block: block,
+        )+
+    ) => {
+        $(
+            impl<$($gen)*> $crate::HeapSize for $typ
+            where
+                $inner: $crate::HeapSize,
+            {
+                $(#[$attr])*
+                fn heap_size(&$self_) -> usize {
+                    let inner: &$inner = $heap_size_block;
+                    <$inner as $crate::HeapSize>::heap_size(inner)
+                }
+            }
+            impl<$($gen)*> $crate::ShallowHeapSize for $typ
+            where
+                $inner: $crate::ShallowHeapSize,
+            {
+                fn shallow_heap_size(&$self_) -> usize {
+                    let inner: &$inner = $heap_size_block;
+                    <$inner as $crate::ShallowHeapSize>::shallow_heap_size(inner)
+                }
+            }
+        )+
+    }
+}
@@ -1,43 +1,66 @@
 mod vec_box_string {
-    use alloc::{boxed::Box, string::String, vec::Vec};
+    use alloc::{
+        boxed::Box,
+        mem::{size_of, size_of_val},
+        string::String,
+        vec::Vec,
+    };
     use std as alloc;
 
-    use crate::HeapSize;
+    use crate::{
+        elements_heap_size, helpers::buffer_bytes, traverse::indirect_heap_size, HeapSize,
+        ShallowHeapSize,
+    };
 
     // Do not impl HeapSize for [T], because type coercions make it error-prone.
     // E.g., Vec[u8] may be casted to &[u8] which does not own any byte in heap.
+    // Use `crate::owned_slice_heap_size` if the slice is known to be owned.
     impl<T: HeapSize> HeapSize for Vec<T> {
         fn heap_size(&self) -> usize {
-            let cap_bytes = self.capacity() * core::mem::size_of::<T>();
-            if self.is_empty() {
-                cap_bytes
-            } else {
-                // Prefer an approximation of its actually heap size, because we
-                // want the time complexity to be O(1).
-                self.len() * self[0].heap_size() + cap_bytes
-            }
+            let cap_bytes = buffer_bytes(self.as_ptr(), self.capacity() * size_of::<T>());
+            cap_bytes + elements_heap_size::<T>(self.len(), self.iter().map(T::heap_size))
         }
     }
 
     impl<T: HeapSize> HeapSize for Box<T> {
         fn heap_size(&self) -> usize {
             // A Box<T> stores the entire T in heap.
-            T::memory_size(self.as_ref())
+            indirect_heap_size(self.as_ref(), false, || {
+                let bytes = buffer_bytes(self.as_ref(), size_of::<T>());
+                bytes + T::heap_size(self.as_ref())
+            })
         }
     }
 
     impl<T: HeapSize> HeapSize for Box<[T]> {
         fn heap_size(&self) -> usize {
-            if self.is_empty() {
-                0
-            } else {
-                self.len() * T::memory_size(&self[0])
-            }
+            indirect_heap_size(self.as_ref(), false, || {
+                let bytes = buffer_bytes(self.as_ptr(), size_of_val::<[T]>(self));
+                bytes + elements_heap_size::<T>(self.len(), self.iter().map(T::heap_size))
+            })
         }
     }
 
     impl HeapSize for String {
         fn heap_size(&self) -> usize {
+            buffer_bytes(self.as_ptr(), self.capacity())
+        }
+    }
+
+    impl<T> ShallowHeapSize for Vec<T> {
+        fn shallow_heap_size(&self) -> usize {
+            self.capacity() * core::mem::size_of::<T>()
+        }
+    }
+
+    impl<T: ?Sized> ShallowHeapSize for Box<T> {
+        fn shallow_heap_size(&self) -> usize {
+            core::mem::size_of_val(self.as_ref())
+        }
+    }
+
+    impl ShallowHeapSize for String {
+        fn shallow_heap_size(&self) -> usize {
             self.capacity()
         }
     }
@@ -62,6 +85,10 @@ mod vec_box_string {
             vv.capacity() * core::mem::size_of::<Vec<u64>>() + v_heap_size,
             vv.heap_size()
         );
+        assert_eq!(
+            vv.capacity() * core::mem::size_of::<Vec<u64>>(),
+            vv.shallow_heap_size()
+        );
     }
 
     #[test]
@@ -77,6 +104,23 @@ mod vec_box_string {
 
         let bdst: Box<[u64]> = Box::new([0u64; 100]) as _;
         assert_eq!(core::mem::size_of::<u64>() * bdst.len(), bdst.heap_size());
+        assert_eq!(bdst.heap_size(), bdst.shallow_heap_size());
+
+        let bv = Box::new(vec![0u8; 8]);
+        assert_eq!(core::mem::size_of::<Vec<u8>>(), bv.shallow_heap_size());
+        let bs: Box<str> = Box::from("abc");
+        assert_eq!(3, bs.shallow_heap_size());
+    }
+
+    #[test]
+    fn test_owned_slice() {
+        let v: Vec<Box<u64>> = vec![Box::new(1), Box::new(2)];
+        let b: Box<[Box<u64>]> = v.clone().into_boxed_slice();
+        assert_eq!(b.heap_size(), crate::owned_slice_heap_size(v.as_slice()));
+        assert_eq!(0, <&[Box<u64>]>::heap_size(&v.as_slice()));
+
+        let empty: &[u64] = &[];
+        assert_eq!(0, crate::owned_slice_heap_size(empty));
     }
 
     #[test]
@@ -87,6 +131,7 @@ mod vec_box_string {
 
         string.reserve(100);
         assert_eq!(string.capacity(), string.heap_size());
+        assert_eq!(string.capacity(), string.shallow_heap_size());
     }
 }
 
@@ -94,7 +139,7 @@ mod borrow {
     use alloc::borrow::Cow;
     use std as alloc;
 
-    use crate::HeapSize;
+    use crate::{HeapSize, ShallowHeapSize};
 
     impl<B> HeapSize for Cow<'_, B>
     where
@@ -109,89 +154,128 @@ mod borrow {
         }
     }
 
+    impl<B> ShallowHeapSize for Cow<'_, B>
+    where
+        B: ?Sized + ToOwned,
+        <B as ToOwned>::Owned: ShallowHeapSize,
+    {
+        fn shallow_heap_size(&self) -> usize {
+            match self {
+                alloc::borrow::Cow::Borrowed(_) => 0,
+                alloc::borrow::Cow::Owned(b) => b.shallow_heap_size(),
+            }
+        }
+    }
+
     #[test]
     fn test_cow() {
         let mut cow = Cow::Borrowed("abc");
         assert_eq!(0, cow.heap_size());
         cow.to_mut();
         assert_eq!(3, cow.heap_size());
+        assert_eq!(3, cow.shallow_heap_size());
     }
 }
 
 mod collections {
-    use alloc::{
-        collections::{BTreeMap, BTreeSet, BinaryHeap, LinkedList, VecDeque},
-        mem::size_of,
-    };
+    use alloc::collections::{BTreeMap, BTreeSet, BinaryHeap, LinkedList, VecDeque};
+    #[cfg(test)]
+    use alloc::mem::size_of;
     use std as alloc;
 
-    use crate::HeapSize;
+    use crate::{
+        contiguous_heap_size, contiguous_heap_size_at, elements_heap_size, helpers::buffer_bytes,
+        node_based_heap_size, HeapSize, ShallowHeapSize,
+    };
+
+    /// Return the start of the buffer of a `VecDeque`.
+    ///
+    /// `VecDeque` does not expose its buffer, but its second slice starts at
+    /// the buffer, even if it is empty. It is only trusted if the first slice
+    /// lies within the buffer.
+    fn buffer_start<T>(deque: &VecDeque<T>) -> Option<*const T> {
+        let (front, back) = deque.as_slices();
+        let start = back.as_ptr() as usize;
+        let end = start.checked_add(deque.capacity() * core::mem::size_of::<T>())?;
+        let front_start = front.as_ptr() as usize;
+        let front_end = front_start + core::mem::size_of_val(front);
+        (start <= front_start && front_end <= end).then_some(back.as_ptr())
+    }
 
+    /// A deque is counted by resident pages or measured by the allocator like
+    /// a `Vec`, see `buffer_start`.
     impl<T: HeapSize> HeapSize for VecDeque<T> {
         fn heap_size(&self) -> usize {
-            let cap_bytes = self.capacity() * size_of::<T>();
-            if self.is_empty() {
-                cap_bytes
-            } else {
-                // Prefer an approximation of its actually heap size, because we
-                // want the time complexity to be O(1).
-                self.len() * self[0].heap_size() + cap_bytes
-            }
+            let cap_bytes = self.capacity() * core::mem::size_of::<T>();
+            let cap_bytes = match buffer_start(self) {
+                Some(start) => buffer_bytes(start, cap_bytes),
+                None => cap_bytes,
+            };
+            cap_bytes + elements_heap_size::<T>(self.len(), self.iter().map(T::heap_size))
         }
     }
 
     impl<K: HeapSize, V: HeapSize> HeapSize for BTreeMap<K, V> {
         fn heap_size(&self) -> usize {
-            if self.is_empty() {
-                0
-            } else {
-                let kv = self.iter().next().unwrap();
-                // Prefer an approximation of its actually heap size, because we
-                // want the time complexity to be O(1).
-                self.len() * (kv.0.memory_size() + kv.1.memory_size())
-            }
+            let kvs = self.iter().map(|(k, v)| k.memory_size() + v.memory_size());
+            elements_heap_size::<(K, V)>(self.len(), kvs)
         }
     }
 
     impl<T: HeapSize> HeapSize for BTreeSet<T> {
         fn heap_size(&self) -> usize {
-            if self.is_empty() {
-                0
-            } else {
-                let t = self.iter().next().unwrap();
-                // Prefer an approximation of its actually heap size, because we
-                // want the time complexity to be O(1).
-                self.len() * t.memory_size()
-            }
+            node_based_heap_size(self.len(), 0, self)
         }
     }
 
     impl<T: HeapSize> HeapSize for LinkedList<T> {
         fn heap_size(&self) -> usize {
-            if self.is_empty() {
-                0
-            } else {
-                let t = self.iter().next().unwrap();
-                // Prefer an approximation of its actually heap size, because we
-                // want the time complexity to be O(1).
-                self.len() * t.memory_size()
-            }
+            node_based_heap_size(self.len(), 0, self)
         }
     }
 
+    /// The greatest element is the first one of the buffer, so a heap that is
+    /// not empty is counted by resident pages or measured by the allocator
+    /// like a `Vec`.
     impl<T: HeapSize> HeapSize for BinaryHeap<T> {
         fn heap_size(&self) -> usize {
-            let cap_bytes = self.capacity() * size_of::<T>();
-            if self.is_empty() {
-                cap_bytes
-            } else {
-                // Prefer an approximation of its actually heap size, because we
-                // want the time complexity to be O(1).
-                self.len() * self.peek().unwrap().heap_size() + cap_bytes
+            match self.peek() {
+                Some(first) => contiguous_heap_size_at(first, self.capacity(), self.len(), self),
+                None => contiguous_heap_size(self.capacity(), 0, self),
             }
         }
     }
 
+    impl<T> ShallowHeapSize for VecDeque<T> {
+        fn shallow_heap_size(&self) -> usize {
+            self.capacity() * core::mem::size_of::<T>()
+        }
+    }
+
+    impl<K, V> ShallowHeapSize for BTreeMap<K, V> {
+        fn shallow_heap_size(&self) -> usize {
+            self.len() * (core::mem::size_of::<K>() + core::mem::size_of::<V>())
+        }
+    }
+
+    impl<T> ShallowHeapSize for BTreeSet<T> {
+        fn shallow_heap_size(&self) -> usize {
+            self.len() * core::mem::size_of::<T>()
+        }
+    }
+
+    impl<T> ShallowHeapSize for LinkedList<T> {
+        fn shallow_heap_size(&self) -> usize {
+            self.len() * core::mem::size_of::<T>()
+        }
+    }
+
+    impl<T> ShallowHeapSize for BinaryHeap<T> {
+        fn shallow_heap_size(&self) -> usize {
+            self.capacity() * core::mem::size_of::<T>()
+        }
+    }
+
     #[test]
     fn test_vec_deque() {
         let mut v: VecDeque<Box<[u8; 8]>> = VecDeque::new();
@@ -206,6 +290,25 @@ mod collections {
             size_of::<Box<[u8; 8]>>() * v.capacity() + b.heap_size(),
             v.heap_size()
         );
+        assert_eq!(
+            size_of::<Box<[u8; 8]>>() * v.capacity(),
+            v.shallow_heap_size()
+        );
+
+        // The second slice starts at the buffer, wrapped or not.
+        let d: VecDeque<u32> = VecDeque::with_capacity(8);
+        let start = buffer_start(&d).unwrap();
+        assert_eq!(Vec::from(d).as_ptr(), start);
+        let mut d: VecDeque<u32> = VecDeque::with_capacity(8);
+        d.extend([1, 2, 3]);
+        d.pop_front();
+        let start = buffer_start(&d).unwrap();
+        assert_eq!(Vec::from(d).as_ptr(), start);
+        let mut d: VecDeque<u32> = VecDeque::with_capacity(8);
+        d.extend(0..8);
+        d.rotate_left(3);
+        let start = buffer_start(&d).unwrap();
+        assert_eq!(Vec::from(d).as_ptr(), start);
     }
 
     #[test]
@@ -217,11 +320,16 @@ mod collections {
         assert_eq!(size_of::<u64>() + b.memory_size(), map.heap_size());
         map.insert(1u64, b.clone());
         assert_eq!(2 * (size_of::<u64>() + b.memory_size()), map.heap_size());
+        assert_eq!(
+            2 * (size_of::<u64>() + size_of::<Box<[u8; 8]>>()),
+            map.shallow_heap_size()
+        );
 
         let mut set: BTreeSet<Box<[u8; 8]>> = BTreeSet::new();
         assert_eq!(0, set.heap_size());
         set.insert(b.clone());
         assert_eq!(b.memory_size(), set.heap_size());
+        assert_eq!(size_of::<Box<[u8; 8]>>(), set.shallow_heap_size());
     }
 
     #[test]
@@ -232,6 +340,7 @@ mod collections {
         list.push_back(b.clone());
         list.push_back(b.clone());
         assert_eq!(2 * b.memory_size(), list.heap_size());
+        assert_eq!(2 * size_of::<Box<[u8; 8]>>(), list.shallow_heap_size());
     }
 
     #[test]
@@ -249,30 +358,49 @@ mod collections {
         assert_eq!(
             size_of::<Box<[u8; 8]>>() * heap.capacity() + b.heap_size(),
             heap.heap_size()
-        )
+        );
+        assert_eq!(
+            size_of::<Box<[u8; 8]>>() * heap.capacity(),
+            heap.shallow_heap_size()
+        );
+
+        // The greatest element is at the start of the buffer.
+        let heap = BinaryHeap::from(vec![1u32, 5, 3, 4]);
+        let first: *const u32 = heap.peek().unwrap();
+        assert_eq!(heap.into_vec().as_ptr(), first);
     }
 }
 
 mod rc {
-    use alloc::rc::{Rc, Weak};
+    use alloc::{
+        mem::{size_of, size_of_val},
+        rc::{Rc, Weak},
+    };
     use std as alloc;
 
-    use crate::HeapSize;
+    use crate::{
+        elements_heap_size,
+        measure::{counted_alloc, measured_bytes},
+        retained::shared_heap_size,
+        HeapSize, ShallowHeapSize,
+    };
 
     impl<T: HeapSize> HeapSize for Rc<T> {
         fn heap_size(&self) -> usize {
             // Rc is a pointer to a Box<T>, so the entire T is in heap.
-            T::memory_size(self.as_ref())
+            shared_heap_size(self.as_ref(), Rc::strong_count(self), || {
+                let bytes = measured_bytes(counted_alloc(self.as_ref()), size_of::<T>());
+                bytes + T::heap_size(self.as_ref())
+            })
         }
     }
 
     impl<T: HeapSize> HeapSize for Rc<[T]> {
         fn heap_size(&self) -> usize {
-            if self.is_empty() {
-                0
-            } else {
-                self.len() * T::memory_size(&self[0])
-            }
+            shared_heap_size(self.as_ref(), Rc::strong_count(self), || {
+                let bytes = measured_bytes(counted_alloc(self.as_ref()), size_of_val::<[T]>(self));
+                bytes + elements_heap_size::<T>(self.len(), self.iter().map(T::heap_size))
+            })
         }
     }
 
@@ -283,6 +411,19 @@ mod rc {
         }
     }
 
+    impl<T: ?Sized> ShallowHeapSize for Rc<T> {
+        fn shallow_heap_size(&self) -> usize {
+            core::mem::size_of_val(self.as_ref())
+        }
+    }
+
+    impl<T: ?Sized> ShallowHeapSize for Weak<T> {
+        /// Always return 0, because it does not own `T`.
+        fn shallow_heap_size(&self) -> usize {
+            0
+        }
+    }
+
     #[test]
     fn test_rc() {
         let b = Box::new([0u8; 8]);
@@ -294,29 +435,55 @@ mod rc {
 
         let rc: Rc<[u8]> = Rc::new([0u8; 8]) as _;
         assert_eq!(8, rc.heap_size());
+        assert_eq!(8, rc.shallow_heap_size());
+        assert_eq!(0, weak.shallow_heap_size());
+    }
+
+    #[test]
+    fn test_rc_proportional() {
+        use crate::traverse::{with_shared, Shared};
+
+        let rc: Rc<[u8]> = Rc::new([0u8; 8]) as _;
+        let rc2 = rc.clone();
+        with_shared(Shared::Proportional, || {
+            assert_eq!(4, rc.heap_size());
+            assert_eq!(4, rc2.heap_size());
+        });
+        drop(rc2);
+        with_shared(Shared::Proportional, || assert_eq!(8, rc.heap_size()));
     }
 }
 
 mod sync {
-    use alloc::sync::{Arc, Weak};
+    use alloc::{
+        mem::{size_of, size_of_val},
+        sync::{Arc, Weak},
+    };
     use std as alloc;
 
-    use crate::HeapSize;
+    use crate::{
+        elements_heap_size,
+        measure::{counted_alloc, measured_bytes},
+        retained::shared_heap_size,
+        HeapSize, ShallowHeapSize,
+    };
 
     impl<T: HeapSize> HeapSize for Arc<T> {
         fn heap_size(&self) -> usize {
             // Arc is a pointer to a Box<T>, so the entire T is in heap.
-            T::memory_size(self.as_ref())
+            shared_heap_size(self.as_ref(), Arc::strong_count(self), || {
+                let bytes = measured_bytes(counted_alloc(self.as_ref()), size_of::<T>());
+                bytes + T::heap_size(self.as_ref())
+            })
         }
     }
 
     impl<T: HeapSize> HeapSize for Arc<[T]> {
         fn heap_size(&self) -> usize {
-            if self.is_empty() {
-                0
-            } else {
-                self.len() * T::memory_size(&self[0])
-            }
+            shared_heap_size(self.as_ref(), Arc::strong_count(self), || {
+                let bytes = measured_bytes(counted_alloc(self.as_ref()), size_of_val::<[T]>(self));
+                bytes + elements_heap_size::<T>(self.len(), self.iter().map(T::heap_size))
+            })
         }
     }
 
@@ -327,6 +494,19 @@ mod sync {
         }
     }
 
+    impl<T: ?Sized> ShallowHeapSize for Arc<T> {
+        fn shallow_heap_size(&self) -> usize {
+            core::mem::size_of_val(self.as_ref())
+        }
+    }
+
+    impl<T: ?Sized> ShallowHeapSize for Weak<T> {
+        /// Always return 0, because it does not own `T`.
+        fn shallow_heap_size(&self) -> usize {
+            0
+        }
+    }
+
     #[test]
     fn test_arc() {
         let b = Box::new([0u8; 8]);
@@ -338,5 +518,20 @@ mod sync {
 
         let rc: Arc<[u8]> = Arc::new([0u8; 8]) as _;
         assert_eq!(8, rc.heap_size());
+        assert_eq!(8, rc.shallow_heap_size());
+        assert_eq!(0, weak.shallow_heap_size());
+    }
+
+    #[test]
+    fn test_arc_proportional() {
+        use crate::traverse::{with_shared, Shared};
+
+        let b = Box::new([0u8; 8]);
+        let arc = Arc::new(b.clone());
+        let arcs = [arc.clone(), arc.clone(), arc.clone(), arc];
+        with_shared(Shared::Proportional, || {
+            assert_eq!(b.memory_size() / 4, arcs[0].heap_size());
+        });
+        assert_eq!(b.memory_size(), arcs[0].heap_size());
     }
 }
@@ -1,5 +1,126 @@
+use crate::{
+    kind::{kind_heap_size, MemoryKind},
+    resident::resident_bytes,
+};
+
 impl crate::HeapSize for bytes::Bytes {
+    /// Return the number of bytes it views.
+    ///
+    /// [`Shared::Proportional`](crate::traverse::Shared::Proportional) mode is
+    /// not supported, because `Bytes` does not expose its reference count. A
+    /// `Bytes` is always charged the whole view.
+    ///
+    /// A `Bytes` of static data, e.g., by `Bytes::from_static`, is tagged as
+    /// [`MemoryKind::Static`], any other `Bytes` that is not unique as
+    /// [`MemoryKind::Shared`]. Static data is told by its address within the
+    /// image of the binary, on Linux only. Elsewhere, or for static data of
+    /// another shared object, tag such fields by
+    /// `#[heap_size(kind = "static")]`, which overrides the shared tag.
+    ///
+    /// Large views are counted by their resident pages within
+    /// [`resident`](crate::resident::resident). A view may not start at its
+    /// allocation, so it is not measured by the allocator.
     fn heap_size(&self) -> usize {
+        let bytes = || resident_bytes(self.as_ptr(), self.len());
+        if self.is_unique() {
+            bytes()
+        } else if is_static(self.as_ptr()) {
+            kind_heap_size(MemoryKind::Static, || self.len())
+        } else {
+            kind_heap_size(MemoryKind::Shared, bytes)
+        }
+    }
+}
+
+/// Whether `ptr` points into the image of the binary, which holds the static
+/// data, e.g., of `Bytes::from_static`. The heap never lies within it.
+#[cfg(target_os = "linux")]
+fn is_static(ptr: *const u8) -> bool {
+    extern "C" {
+        // Defined by the linker at the start and the end of the image.
+        static __ehdr_start: u8;
+        static _end: u8;
+    }
+    // SAFETY: only the addresses of the symbols are taken.
+    let image = unsafe { std::ptr::addr_of!(__ehdr_start)..std::ptr::addr_of!(_end) };
+    image.contains(&ptr)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_static(_ptr: *const u8) -> bool {
+    false
+}
+
+impl crate::ShallowHeapSize for bytes::Bytes {
+    fn shallow_heap_size(&self) -> usize {
         self.len()
     }
 }
+
+impl crate::HeapSize for bytes::BytesMut {
+    fn heap_size(&self) -> usize {
+        // `BytesMut` may not start at its allocation, so it is not measured.
+        resident_bytes(self.as_ptr(), self.capacity())
+    }
+}
+
+impl crate::ShallowHeapSize for bytes::BytesMut {
+    fn shallow_heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+#[test]
+fn test_bytes() {
+    use crate::HeapSize;
+
+    let b = bytes::Bytes::from(vec![0u8; 8]);
+    assert_eq!(8, b.heap_size());
+    let b2 = b.clone();
+    assert_eq!(8, b.heap_size());
+    drop(b2);
+
+    let breakdown = b.heap_size_by_kind();
+    assert_eq!(8, breakdown.get(MemoryKind::Heap));
+    let b2 = b.clone();
+    let breakdown = b2.heap_size_by_kind();
+    assert_eq!(8, breakdown.get(MemoryKind::Shared));
+    assert_eq!(0, breakdown.get(MemoryKind::Heap));
+
+    let s = bytes::Bytes::from_static(b"hello world");
+    assert_eq!(11, s.heap_size());
+    let breakdown = s.heap_size_by_kind();
+    if cfg!(target_os = "linux") {
+        assert_eq!(11, breakdown.get(MemoryKind::Static));
+        assert_eq!(0, breakdown.get(MemoryKind::Shared));
+    }
+    let s = bytes::Bytes::from(String::from("hello world")).slice(1..);
+    let _s2 = s.clone();
+    assert_eq!(10, s.heap_size_by_kind().get(MemoryKind::Shared));
+}
+
+#[test]
+fn test_bytes_mut() {
+    use crate::HeapSize;
+
+    let b = bytes::BytesMut::with_capacity(8);
+    assert_eq!(b.capacity(), b.heap_size());
+    assert_eq!(b.capacity(), b.resident_heap_size());
+}
+
+#[test]
+fn test_bytes_resident() {
+    use crate::HeapSize;
+
+    let b = bytes::Bytes::from(vec![1u8; 1 << 20]);
+    assert_eq!(b.heap_size(), b.resident_heap_size());
+    let b2 = b.slice(1..);
+    assert_eq!(b2.len(), b2.resident_heap_size());
+
+    // Zeroed pages are mapped lazily.
+    let b = bytes::Bytes::from(vec![0u8; 64 << 20]);
+    if cfg!(target_os = "linux") {
+        let untouched = b.resident_heap_size();
+        assert!(untouched < b.len() / 2, "{}", untouched);
+    }
+}
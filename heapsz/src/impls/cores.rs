@@ -1,5 +1,5 @@
 mod primitive {
-    use crate::HeapSize;
+    use crate::{elements_heap_size, HeapSize, ShallowHeapSize};
 
     impl_heap_size! {
         zero gen_test
@@ -16,69 +16,41 @@ mod primitive {
 
     impl<T: HeapSize, const N: usize> HeapSize for [T; N] {
         fn heap_size(&self) -> usize {
-            if self.is_empty() {
-                0
-            } else {
-                // Prefer an approximation of its actually heap size, because we
-                // want the time complexity to be O(1).
-                self.len() * self[0].heap_size()
-            }
+            elements_heap_size::<T>(N, self.iter().map(T::heap_size))
         }
     }
 
-    impl<A: HeapSize, B: HeapSize> HeapSize for (A, B) {
-        fn heap_size(&self) -> usize {
-            self.0.heap_size() + self.1.heap_size()
+    impl<T: ShallowHeapSize, const N: usize> ShallowHeapSize for [T; N] {
+        fn shallow_heap_size(&self) -> usize {
+            self.iter().map(T::shallow_heap_size).sum()
         }
     }
 
-    impl<A: HeapSize, B: HeapSize, C: HeapSize> HeapSize for (A, B, C) {
-        fn heap_size(&self) -> usize {
-            self.0.heap_size() + self.1.heap_size() + self.2.heap_size()
-        }
-    }
-
-    impl<A: HeapSize, B: HeapSize, C: HeapSize, D: HeapSize> HeapSize for (A, B, C, D) {
-        fn heap_size(&self) -> usize {
-            self.0.heap_size() + self.1.heap_size() + self.2.heap_size() + self.3.heap_size()
-        }
-    }
+    macro_rules! impl_tuple {
+        ( $( ($($name: ident $idx: tt),+) )+ ) => {
+            $(
+                impl<$($name: HeapSize),+> HeapSize for ($($name,)+) {
+                    fn heap_size(&self) -> usize {
+                        0 $(+ self.$idx.heap_size())+
+                    }
+                }
 
-    impl<A: HeapSize, B: HeapSize, C: HeapSize, D: HeapSize, E: HeapSize> HeapSize for (A, B, C, D, E) {
-        fn heap_size(&self) -> usize {
-            self.0.heap_size()
-                + self.1.heap_size()
-                + self.2.heap_size()
-                + self.3.heap_size()
-                + self.4.heap_size()
-        }
+                impl<$($name: ShallowHeapSize),+> ShallowHeapSize for ($($name,)+) {
+                    fn shallow_heap_size(&self) -> usize {
+                        0 $(+ self.$idx.shallow_heap_size())+
+                    }
+                }
+            )+
+        };
     }
 
-    impl<A: HeapSize, B: HeapSize, C: HeapSize, D: HeapSize, E: HeapSize, F: HeapSize> HeapSize
-        for (A, B, C, D, E, F)
-    {
-        fn heap_size(&self) -> usize {
-            self.0.heap_size()
-                + self.1.heap_size()
-                + self.2.heap_size()
-                + self.3.heap_size()
-                + self.4.heap_size()
-                + self.5.heap_size()
-        }
-    }
-
-    impl<A: HeapSize, B: HeapSize, C: HeapSize, D: HeapSize, E: HeapSize, F: HeapSize, G: HeapSize>
-        HeapSize for (A, B, C, D, E, F, G)
-    {
-        fn heap_size(&self) -> usize {
-            self.0.heap_size()
-                + self.1.heap_size()
-                + self.2.heap_size()
-                + self.3.heap_size()
-                + self.4.heap_size()
-                + self.5.heap_size()
-                + self.6.heap_size()
-        }
+    impl_tuple! {
+        (A 0, B 1)
+        (A 0, B 1, C 2)
+        (A 0, B 1, C 2, D 3)
+        (A 0, B 1, C 2, D 3, E 4)
+        (A 0, B 1, C 2, D 3, E 4, F 5)
+        (A 0, B 1, C 2, D 3, E 4, F 5, G 6)
     }
 
     #[test]
@@ -86,27 +58,63 @@ mod primitive {
         let b = Box::new([0u8; 8]);
         let a = [b.clone(), b.clone(), b.clone(), b.clone(), b.clone()];
         assert_eq!(b.heap_size() * a.len(), a.heap_size());
+        assert_eq!(b.shallow_heap_size() * a.len(), a.shallow_heap_size());
+    }
+
+    #[test]
+    fn test_tuple() {
+        let v = vec![0u8; 8];
+        let s = vec!["x".repeat(16)];
+        let t = (v.clone(), s.clone(), 1u64);
+        assert_eq!(v.heap_size() + s.heap_size(), t.heap_size());
+        assert_eq!(
+            v.shallow_heap_size() + s.shallow_heap_size(),
+            t.shallow_heap_size()
+        );
     }
 }
 
 mod cell {
+    use core::cell::RefCell;
+
+    use crate::{estimate, HeapSize, ShallowHeapSize};
 
     impl_heap_size! {
         container
         self,
-        core::cell::Cell<T> => {
+        [T] core::cell::Cell<T> => T {
             // SAFETY: This can cause data races if a separate thread calls
             // Cell::replace, but `Cell` is `!Sync` so this won't happen.
             unsafe { &*self.as_ptr() }
         },
+    }
+
+    impl<T: HeapSize> HeapSize for RefCell<T> {
         /// Return the number of bytes it owns in heap.
         ///
         /// # Panics
         ///
+        /// Panics if the value is currently mutably borrowed, unless it is in
+        /// an estimate, where the value is unknown.
+        fn heap_size(&self) -> usize {
+            if estimate::is_estimating() {
+                return self
+                    .try_borrow()
+                    .map_or_else(|_| estimate::unknown(), |t| t.heap_size());
+            }
+            self.borrow().heap_size()
+        }
+    }
+
+    impl<T: ShallowHeapSize> ShallowHeapSize for RefCell<T> {
+        /// Return the number of bytes it directly owns on the heap.
+        ///
+        /// # Panics
+        ///
         /// Panics if the value is currently mutably borrowed.
-        core::cell::RefCell<T> => {
-           &*self.borrow()
-        },
+        fn shallow_heap_size(&self) -> usize {
+            self.borrow().shallow_heap_size()
+        }
     }
 
     #[test]
@@ -116,6 +124,7 @@ mod cell {
         let b = Box::new([0u8; 8]);
         let c = core::cell::Cell::new(b.clone());
         assert_eq!(b.heap_size(), c.heap_size());
+        assert_eq!(8, crate::ShallowHeapSize::shallow_heap_size(&c));
     }
 
     #[test]
@@ -125,6 +134,14 @@ mod cell {
         let b = Box::new([0u8; 8]);
         let c = core::cell::RefCell::new(b.clone());
         assert_eq!(b.heap_size(), c.heap_size());
+        assert_eq!(8, crate::ShallowHeapSize::shallow_heap_size(&c));
+
+        let _guard = c.borrow_mut();
+        let estimate = c.heap_size_estimate();
+        assert_eq!(
+            (0, 0, usize::MAX),
+            (estimate.value, estimate.lower, estimate.upper)
+        );
     }
 }
 
@@ -139,6 +156,20 @@ mod marker {
             0
         }
     }
+
+    impl<T: ?Sized> crate::ShallowHeapSize for core::marker::PhantomData<T> {
+        fn shallow_heap_size(&self) -> usize {
+            0
+        }
+    }
+}
+
+mod mem {
+    impl_heap_size! {
+        container
+        self,
+        [T] core::mem::ManuallyDrop<T> => T { &**self },
+    }
 }
 
 mod time {
@@ -148,10 +179,95 @@ mod time {
     }
 }
 
+mod reference {
+    use crate::{HeapSize, ShallowHeapSize};
+
+    // Do not impl HeapSize for &T, it would shadow `T::heap_size` in method
+    // calls on `&&T`. Fields of borrows are counted as 0 by
+    // `#[derive(HeapSize)]` instead.
+    impl HeapSize for &str {
+        /// Always return 0, because a borrow does not own the string.
+        fn heap_size(&self) -> usize {
+            0
+        }
+    }
+
+    impl HeapSize for &mut str {
+        /// Always return 0, because a borrow does not own the string.
+        fn heap_size(&self) -> usize {
+            0
+        }
+    }
+
+    impl<T> HeapSize for &[T] {
+        /// Always return 0, because a borrow does not own the slice.
+        fn heap_size(&self) -> usize {
+            0
+        }
+    }
+
+    impl<T> HeapSize for &mut [T] {
+        /// Always return 0, because a borrow does not own the slice.
+        fn heap_size(&self) -> usize {
+            0
+        }
+    }
+
+    impl ShallowHeapSize for &str {
+        /// Always return 0, because a borrow does not own the string.
+        fn shallow_heap_size(&self) -> usize {
+            0
+        }
+    }
+
+    impl ShallowHeapSize for &mut str {
+        /// Always return 0, because a borrow does not own the string.
+        fn shallow_heap_size(&self) -> usize {
+            0
+        }
+    }
+
+    impl<T> ShallowHeapSize for &[T] {
+        /// Always return 0, because a borrow does not own the slice.
+        fn shallow_heap_size(&self) -> usize {
+            0
+        }
+    }
+
+    impl<T> ShallowHeapSize for &mut [T] {
+        /// Always return 0, because a borrow does not own the slice.
+        fn shallow_heap_size(&self) -> usize {
+            0
+        }
+    }
+
+    #[test]
+    fn test_reference() {
+        let v = vec![0u8; 8];
+        assert_eq!(0, <&[u8]>::heap_size(&v.as_slice()));
+        assert_eq!(0, <&str>::heap_size(&"abc"));
+        assert_eq!(0, <&[u8]>::shallow_heap_size(&v.as_slice()));
+
+        let mut b = [0u8; 8];
+        assert_eq!(0, <&mut [u8]>::heap_size(&b.as_mut_slice()));
+
+        let borrowed: Vec<&[u8]> = vec![&v, &v];
+        assert_eq!(
+            borrowed.capacity() * core::mem::size_of::<&[u8]>(),
+            borrowed.heap_size()
+        );
+
+        // Method calls on `&&Vec<u8>` resolve to `Vec<u8>`.
+        let refs: Vec<&Vec<u8>> = vec![&v, &v];
+        let owned = refs.iter().map(|v| v.heap_size()).sum::<usize>();
+        assert_eq!(2 * v.heap_size(), owned);
+    }
+}
+
 mod option_result {
     use core::{option::Option, result::Result};
 
-    use crate::HeapSize;
+    use crate::{HeapSize, ShallowHeapSize};
 
     impl<T: HeapSize> HeapSize for Option<T> {
         fn heap_size(&self) -> usize {
@@ -175,13 +291,36 @@ mod option_result {
         }
     }
 
+    impl<T: ShallowHeapSize> ShallowHeapSize for Option<T> {
+        fn shallow_heap_size(&self) -> usize {
+            match self {
+                Option::Some(t) => t.shallow_heap_size(),
+                Option::None => 0,
+            }
+        }
+    }
+
+    impl<T: ShallowHeapSize, E> ShallowHeapSize for Result<T, E> {
+        /// Return the number of bytes it directly owns on the heap.
+        ///
+        /// Note: Err is ignored, the same as `HeapSize`.
+        fn shallow_heap_size(&self) -> usize {
+            match self {
+                Result::Ok(t) => t.shallow_heap_size(),
+                Result::Err(_) => 0,
+            }
+        }
+    }
+
     #[test]
     fn test_option() {
         let b = Box::new([0u8; 8]);
         let mut op = Option::Some(b.clone());
         assert_eq!(b.heap_size(), op.heap_size());
+        assert_eq!(b.shallow_heap_size(), op.shallow_heap_size());
         op.take();
         assert_eq!(0, op.heap_size());
+        assert_eq!(0, op.shallow_heap_size());
     }
 
     #[test]
@@ -189,6 +328,7 @@ mod option_result {
         let b = Box::new([0u8; 8]);
         let res = Result::Ok::<_, ()>(b.clone());
         assert_eq!(b.heap_size(), res.heap_size());
+        assert_eq!(b.shallow_heap_size(), res.shallow_heap_size());
         let res = Result::Err::<(), _>(b);
         assert_eq!(0, res.heap_size());
     }
@@ -230,7 +370,7 @@ mod sync {
 mod task {
     use core::task::Poll;
 
-    use crate::HeapSize;
+    use crate::{HeapSize, ShallowHeapSize};
 
     impl<T: HeapSize> HeapSize for Poll<T> {
         fn heap_size(&self) -> usize {
@@ -241,11 +381,21 @@ mod task {
         }
     }
 
+    impl<T: ShallowHeapSize> ShallowHeapSize for Poll<T> {
+        fn shallow_heap_size(&self) -> usize {
+            match self {
+                Poll::Ready(t) => t.shallow_heap_size(),
+                Poll::Pending => 0,
+            }
+        }
+    }
+
     #[test]
     fn test_cell() {
         let b = Box::new([0u8; 8]);
         let p = Poll::Ready(b.clone());
         assert_eq!(b.heap_size(), p.heap_size());
+        assert_eq!(b.shallow_heap_size(), p.shallow_heap_size());
         let p = Poll::Pending::<Box<[u8; 8]>>;
         assert_eq!(0, p.heap_size());
     }
@@ -6,29 +6,19 @@ macro_rules! impl_heap_size{
         #[test]
         fn test() {
             use $crate::HeapSize;
+            use $crate::ShallowHeapSize;
             $({
                 let v = <$typ as Default>::default();
                 assert_eq!(0, v.heap_size());
+                assert_eq!(0, v.shallow_heap_size());
             })+
         }
     };
     ( zero $($typ: ty,)+ ) => {
-        $(
-            impl $crate::HeapSize for $typ {
-                fn heap_size(&self) -> usize { 0 }
-            }
-        )+
+        $crate::impl_zero!{ $($typ,)+ }
     };
-    ( container $self_: tt, $( $(#[$attr:meta])* $typ: ty => $heap_size_block: block,)+ ) => {
-        $(
-            impl<T: $crate::HeapSize> $crate::HeapSize for $typ {
-                $(#[$attr])*
-                fn heap_size(&$self_) -> usize {
-                    let inner = $heap_size_block;
-                    T::heap_size(inner)
-                }
-            }
-        )+
+    ( container $($tt: tt)+ ) => {
+        $crate::impl_container!{ $($tt)+ }
     }
 }
 
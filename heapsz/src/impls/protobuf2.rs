@@ -19,17 +19,37 @@ impl<T: crate::HeapSize> crate::HeapSize for protobuf2::SingularPtrField<T> {
 
 impl<T: crate::HeapSize> crate::HeapSize for protobuf2::RepeatedField<T> {
     fn heap_size(&self) -> usize {
-        let cap_bytes = self.capacity() * core::mem::size_of::<T>();
-        if self.is_empty() {
-            cap_bytes
-        } else {
-            // Prefer an approximation of its actually heap size, because we
-            // want the time complexity to be O(1).
-            self.len() * self[0].heap_size() + cap_bytes
+        // `RepeatedField` is a wrapper of `Vec<T>`, its slice starts at the
+        // buffer.
+        let ptr = self.as_slice().as_ptr();
+        crate::contiguous_heap_size_at(ptr, self.capacity(), self.len(), self)
+    }
+}
+
+impl<T: crate::ShallowHeapSize> crate::ShallowHeapSize for protobuf2::SingularField<T> {
+    fn shallow_heap_size(&self) -> usize {
+        match self.as_ref() {
+            Some(t) => t.shallow_heap_size(),
+            None => 0,
+        }
+    }
+}
+
+impl<T> crate::ShallowHeapSize for protobuf2::SingularPtrField<T> {
+    fn shallow_heap_size(&self) -> usize {
+        match self.as_ref() {
+            Some(_) => core::mem::size_of::<T>(),
+            None => 0,
         }
     }
 }
 
+impl<T> crate::ShallowHeapSize for protobuf2::RepeatedField<T> {
+    fn shallow_heap_size(&self) -> usize {
+        self.capacity() * core::mem::size_of::<T>()
+    }
+}
+
 impl_heap_size! {
     zero
     protobuf2::UnknownFields,
@@ -5,33 +5,33 @@ mod hash {
 mod collections {
     use std::{collections, mem};
 
-    use crate::HeapSize;
+    use crate::{contiguous_heap_size, elements_heap_size, HeapSize, ShallowHeapSize};
 
     impl<K: HeapSize, V: HeapSize, S> HeapSize for collections::HashMap<K, V, S> {
         fn heap_size(&self) -> usize {
             let cap_bytes = self.capacity() * (mem::size_of::<K>() + mem::size_of::<V>());
-            if self.is_empty() {
-                cap_bytes
-            } else {
-                let kv = self.iter().next().unwrap();
-                // Prefer an approximation of its actually heap size, because we
-                // want the time complexity to be O(1).
-                cap_bytes + self.len() * (kv.0.heap_size() + kv.1.heap_size())
-            }
+            let kvs = self.iter().map(|(k, v)| k.heap_size() + v.heap_size());
+            cap_bytes + elements_heap_size::<(K, V)>(self.len(), kvs)
         }
     }
 
+    /// The table does not expose the start of its allocation, so it is
+    /// counted by its capacity in every mode.
     impl<T: HeapSize, S> HeapSize for collections::HashSet<T, S> {
         fn heap_size(&self) -> usize {
-            let cap_bytes = self.capacity() * mem::size_of::<T>();
-            if self.is_empty() {
-                cap_bytes
-            } else {
-                let k = self.iter().next().unwrap();
-                // Prefer an approximation of its actually heap size, because we
-                // want the time complexity to be O(1).
-                cap_bytes + self.len() * k.heap_size()
-            }
+            contiguous_heap_size(self.capacity(), self.len(), self)
+        }
+    }
+
+    impl<K, V, S> ShallowHeapSize for collections::HashMap<K, V, S> {
+        fn shallow_heap_size(&self) -> usize {
+            self.capacity() * (mem::size_of::<K>() + mem::size_of::<V>())
+        }
+    }
+
+    impl<T, S> ShallowHeapSize for collections::HashSet<T, S> {
+        fn shallow_heap_size(&self) -> usize {
+            self.capacity() * mem::size_of::<T>()
         }
     }
 
@@ -44,6 +44,10 @@ mod collections {
                 + mem::size_of::<[u64; 32]>(),
             map.heap_size()
         );
+        assert_eq!(
+            map.capacity() * (mem::size_of::<u64>() + mem::size_of::<Box<[u64; 32]>>()),
+            map.shallow_heap_size()
+        );
     }
 
     #[test]
@@ -54,20 +58,41 @@ mod collections {
             (set.capacity() * mem::size_of::<Box<[u64; 32]>>()) + mem::size_of::<[u64; 32]>(),
             set.heap_size()
         );
+        assert_eq!(
+            set.capacity() * mem::size_of::<Box<[u64; 32]>>(),
+            set.shallow_heap_size()
+        );
     }
 }
 
 mod io {
-    use crate::HeapSize;
+    use crate::{
+        kind::{kind_heap_size, MemoryKind},
+        HeapSize, ShallowHeapSize,
+    };
 
+    /// Buffers are reserved for I/O, they are tagged as
+    /// [`MemoryKind::Pinned`].
     impl<T: ?Sized> HeapSize for std::io::BufReader<T> {
         fn heap_size(&self) -> usize {
-            self.capacity()
+            kind_heap_size(MemoryKind::Pinned, || self.capacity())
         }
     }
 
     impl<T: ?Sized + std::io::Write> HeapSize for std::io::BufWriter<T> {
         fn heap_size(&self) -> usize {
+            kind_heap_size(MemoryKind::Pinned, || self.capacity())
+        }
+    }
+
+    impl<T: ?Sized> ShallowHeapSize for std::io::BufReader<T> {
+        fn shallow_heap_size(&self) -> usize {
+            self.capacity()
+        }
+    }
+
+    impl<T: ?Sized + std::io::Write> ShallowHeapSize for std::io::BufWriter<T> {
+        fn shallow_heap_size(&self) -> usize {
             self.capacity()
         }
     }
@@ -78,6 +103,7 @@ mod io {
         assert_eq!(s.capacity(), s.heap_size());
         let s = std::io::BufReader::with_capacity(10, std::io::empty());
         assert_eq!(s.capacity(), s.heap_size());
+        assert_eq!(s.capacity(), s.shallow_heap_size());
     }
 
     #[test]
@@ -86,6 +112,7 @@ mod io {
         assert_eq!(s.capacity(), s.heap_size());
         let s = std::io::BufWriter::with_capacity(10, std::io::empty());
         assert_eq!(s.capacity(), s.heap_size());
+        assert_eq!(s.capacity(), s.shallow_heap_size());
     }
 }
 
@@ -100,7 +127,7 @@ mod net {
 }
 
 mod path {
-    use crate::HeapSize;
+    use crate::{HeapSize, ShallowHeapSize};
 
     impl_heap_size! {
         zero
@@ -113,19 +140,26 @@ mod path {
         }
     }
 
+    impl ShallowHeapSize for std::path::PathBuf {
+        fn shallow_heap_size(&self) -> usize {
+            self.capacity()
+        }
+    }
+
     #[test]
     fn test() {
         let s = std::path::PathBuf::new();
         assert_eq!(s.capacity(), s.heap_size());
         let s = std::path::PathBuf::with_capacity(10);
         assert_eq!(s.capacity(), s.heap_size());
+        assert_eq!(s.capacity(), s.shallow_heap_size());
     }
 }
 
 mod sync {
-    use std::sync::{Mutex, RwLock};
+    use std::sync::{Mutex, RwLock, TryLockError};
 
-    use crate::HeapSize;
+    use crate::{estimate, HeapSize, ShallowHeapSize};
 
     impl<T: HeapSize> HeapSize for Mutex<T> {
         /// Return the number of bytes it owns in heap.
@@ -135,7 +169,16 @@ mod sync {
         /// This function might panic when called if 1) the lock is already held
         /// by the current thread or 2) the lock is
         /// [poisoned](https://doc.rust-lang.org/stable/std/sync/struct.Mutex.html#errors).
+        ///
+        /// In an estimate, it never blocks, a locked value is unknown.
         fn heap_size(&self) -> usize {
+            if estimate::is_estimating() {
+                return match self.try_lock() {
+                    Ok(t) => T::memory_size(&t),
+                    Err(TryLockError::Poisoned(e)) => T::memory_size(&e.into_inner()),
+                    Err(TryLockError::WouldBlock) => estimate::unknown(),
+                };
+            }
             T::memory_size(&*self.lock().unwrap())
         }
     }
@@ -148,11 +191,36 @@ mod sync {
         /// This function might panic when called if 1) the lock is already held
         /// by the current thread or 2) the lock is
         /// [poisoned](https://doc.rust-lang.org/stable/std/sync/struct.RwLock.html#errors-1).
+        ///
+        /// In an estimate, it never blocks, a locked value is unknown.
         fn heap_size(&self) -> usize {
+            if estimate::is_estimating() {
+                return match self.try_read() {
+                    Ok(t) => T::memory_size(&t),
+                    Err(TryLockError::Poisoned(e)) => T::memory_size(&e.into_inner()),
+                    Err(TryLockError::WouldBlock) => estimate::unknown(),
+                };
+            }
             T::memory_size(&*self.read().unwrap())
         }
     }
 
+    impl<T> ShallowHeapSize for Mutex<T> {
+        /// Return the number of bytes of `T`, which is counted as owned by the
+        /// lock, the same as `HeapSize`.
+        fn shallow_heap_size(&self) -> usize {
+            std::mem::size_of::<T>()
+        }
+    }
+
+    impl<T> ShallowHeapSize for RwLock<T> {
+        /// Return the number of bytes of `T`, which is counted as owned by the
+        /// lock, the same as `HeapSize`.
+        fn shallow_heap_size(&self) -> usize {
+            std::mem::size_of::<T>()
+        }
+    }
+
     #[test]
     fn test_mutex() {
         let s = Mutex::new([0u64; 32]);
@@ -160,6 +228,13 @@ mod sync {
 
         let s = Mutex::new(Box::new([0u64; 32]));
         assert_eq!(HeapSize::memory_size(&Box::new([0u64; 32])), s.heap_size());
+        assert_eq!(std::mem::size_of::<Box<[u64; 32]>>(), s.shallow_heap_size());
+
+        assert!(s.heap_size_estimate().exact);
+        let _guard = s.lock().unwrap();
+        let estimate = s.heap_size_estimate();
+        assert!(!estimate.exact);
+        assert_eq!(usize::MAX, estimate.upper);
     }
 
     #[test]
@@ -169,6 +244,10 @@ mod sync {
 
         let s = RwLock::new(Box::new([0u64; 32]));
         assert_eq!(HeapSize::memory_size(&Box::new([0u64; 32])), s.heap_size());
+        assert_eq!(std::mem::size_of::<Box<[u64; 32]>>(), s.shallow_heap_size());
+
+        let _guard = s.write().unwrap();
+        assert_eq!(usize::MAX, s.heap_size_estimate().upper);
     }
 }
 
@@ -0,0 +1,149 @@
+//! Reconcile `HeapSize` accounting against jemalloc statistics.
+//!
+//! [`reconcile()`] reads `stats.allocated` and `stats.resident` of jemalloc,
+//! and compares them to the heap sizes of annotated structures, e.g., roots
+//! of a [`Report`](crate::retained::Report). Bytes that no structure explains
+//! are reported as an `unaccounted` line item, so that a new cache nobody
+//! annotated shows up.
+//!
+//! ```no_run
+//! use heapsz::{jemalloc, HeapSize};
+//!
+//! let cache = vec![0u8; 1024];
+//! let index = vec![0u32; 256];
+//! let reconciliation =
+//!     jemalloc::reconcile([("cache", cache.heap_size()), ("index", index.heap_size())]).unwrap();
+//! println!("{reconciliation}");
+//! ```
+
+use std::fmt;
+
+use tikv_jemalloc_ctl::{epoch, stats, Error};
+
+use crate::reconcile;
+pub use crate::reconcile::Item;
+
+/// Process-level statistics of jemalloc.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of bytes allocated by the application, `stats.allocated`.
+    pub allocated: usize,
+    /// The number of bytes in physically resident data pages mapped by the
+    /// allocator, `stats.resident`.
+    pub resident: usize,
+}
+
+impl Stats {
+    /// Read the latest statistics of jemalloc.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if jemalloc statistics can not be read.
+    pub fn read() -> Result<Stats, Error> {
+        // Statistics are cached, advance the epoch to refresh them.
+        epoch::advance()?;
+        Ok(Stats {
+            allocated: stats::allocated::read()?,
+            resident: stats::resident::read()?,
+        })
+    }
+}
+
+/// Accounted line items compared to jemalloc statistics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reconciliation {
+    /// The statistics of jemalloc.
+    pub stats: Stats,
+    /// Accounted line items, in the order they are given.
+    pub items: Vec<Item>,
+    /// The total number of bytes of all items.
+    pub accounted: usize,
+    /// The number of allocated bytes that no item accounts for, it is 0 if
+    /// items account for more than allocated.
+    pub unaccounted: usize,
+}
+
+impl Reconciliation {
+    /// Compare `items`, pairs of names and numbers of bytes, to `stats`.
+    pub fn new<S: Into<String>>(stats: Stats, items: impl IntoIterator<Item = (S, usize)>) -> Self {
+        let (items, accounted) = reconcile::collect(items);
+        Reconciliation {
+            stats,
+            items,
+            accounted,
+            unaccounted: stats.allocated.saturating_sub(accounted),
+        }
+    }
+
+    /// Return the fraction of allocated bytes explained by items.
+    #[must_use]
+    // A fraction does not need every bit of the sizes.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn explained(&self) -> f64 {
+        if self.stats.allocated == 0 {
+            return 1.0;
+        }
+        self.accounted as f64 / self.stats.allocated as f64
+    }
+}
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in &self.items {
+            writeln!(f, "{} {}", item.name, item.bytes)?;
+        }
+        writeln!(f, "unaccounted {}", self.unaccounted)?;
+        writeln!(f, "allocated {}", self.stats.allocated)?;
+        writeln!(f, "resident {}", self.stats.resident)
+    }
+}
+
+/// Read jemalloc statistics, and compare `items`, pairs of names and numbers
+/// of bytes, to them.
+///
+/// # Errors
+///
+/// Returns an error if jemalloc statistics can not be read.
+pub fn reconcile<S: Into<String>>(
+    items: impl IntoIterator<Item = (S, usize)>,
+) -> Result<Reconciliation, Error> {
+    Ok(Reconciliation::new(Stats::read()?, items))
+}
+
+#[test]
+fn test_reconciliation() {
+    let stats = Stats {
+        allocated: 1000,
+        resident: 4096,
+    };
+    let r = Reconciliation::new(stats, [("cache", 600), ("index", 100)]);
+    assert_eq!(700, r.accounted);
+    assert_eq!(300, r.unaccounted);
+    assert!((r.explained() - 0.7).abs() < f64::EPSILON);
+    assert_eq!(
+        "cache 600\nindex 100\nunaccounted 300\nallocated 1000\nresident 4096\n",
+        r.to_string()
+    );
+
+    // Overaccounted.
+    let r = Reconciliation::new(stats, [("cache".to_owned(), 2000)]);
+    assert_eq!(0, r.unaccounted);
+
+    // Compare a retained report.
+    let v = std::rc::Rc::new(vec![0u8; 16]);
+    let mut graph = crate::retained::Graph::new();
+    graph.add_root("v", &v);
+    let report = graph.report();
+    let r = Reconciliation::new(
+        stats,
+        report.roots.iter().map(|e| (e.path.as_str(), e.retained)),
+    );
+    assert_eq!(report.roots[0].retained, r.accounted);
+}
+
+#[test]
+fn test_reconcile() {
+    let r = reconcile([("nothing", 0)]).unwrap();
+    assert_eq!(r.stats.allocated, r.unaccounted);
+    assert!(r.stats.resident >= r.stats.allocated);
+}
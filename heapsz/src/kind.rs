@@ -0,0 +1,278 @@
+//! Break heap sizes down by the kind of memory.
+//!
+//! Not every byte is malloc'ed heap: some buffers wrap mmap'd files, some
+//! are shared by many owners, some point to static data and some are
+//! reserved for I/O. Implementations tag their bytes by [`kind_heap_size`],
+//! and [`HeapSize::heap_size_by_kind`](crate::HeapSize::heap_size_by_kind)
+//! collects them into a [`Breakdown`]. Untagged bytes are
+//! [`MemoryKind::Heap`].
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use heapsz::{kind::MemoryKind, HeapSize};
+//!
+//! let v = (vec![0u8; 16], Arc::new(vec![0u8; 32]));
+//! let breakdown = v.heap_size_by_kind();
+//! assert_eq!(16, breakdown.get(MemoryKind::Heap));
+//! assert_eq!(v.1.heap_size(), breakdown.get(MemoryKind::Shared));
+//! assert_eq!(v.heap_size(), breakdown.total());
+//! ```
+
+use std::{cell::RefCell, fmt};
+
+use crate::modes::{any_installed, Installed};
+
+/// The kind of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[allow(clippy::module_name_repetitions)]
+pub enum MemoryKind {
+    /// Memory allocated by the global allocator.
+    Heap,
+    /// Memory mapped from files, e.g., by `mmap`.
+    Mmap,
+    /// Memory shared by many owners, e.g., the `T` in an `Arc<T>`.
+    Shared,
+    /// Static memory, which is never freed.
+    Static,
+    /// Memory reserved for I/O, e.g., the buffer of a `BufReader`.
+    Pinned,
+}
+
+impl MemoryKind {
+    /// All kinds of memory.
+    pub const ALL: [MemoryKind; 5] = [
+        MemoryKind::Heap,
+        MemoryKind::Mmap,
+        MemoryKind::Shared,
+        MemoryKind::Static,
+        MemoryKind::Pinned,
+    ];
+
+    /// Return the name of the kind, e.g., `mmap`.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MemoryKind::Heap => "heap",
+            MemoryKind::Mmap => "mmap",
+            MemoryKind::Shared => "shared",
+            MemoryKind::Static => "static",
+            MemoryKind::Pinned => "pinned",
+        }
+    }
+}
+
+impl fmt::Display for MemoryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Heap sizes broken down by [`MemoryKind`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Breakdown {
+    bytes: [usize; MemoryKind::ALL.len()],
+}
+
+impl Breakdown {
+    /// Return the number of bytes of `kind`.
+    #[must_use]
+    pub fn get(&self, kind: MemoryKind) -> usize {
+        self.bytes[kind as usize]
+    }
+
+    /// Return the number of bytes of all kinds.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.bytes.iter().sum()
+    }
+
+    /// Iterate over kinds and their number of bytes.
+    pub fn iter(&self) -> impl Iterator<Item = (MemoryKind, usize)> + '_ {
+        MemoryKind::ALL.iter().map(|kind| (*kind, self.get(*kind)))
+    }
+
+    fn add(&mut self, kind: MemoryKind, bytes: usize) {
+        let b = &mut self.bytes[kind as usize];
+        *b = b.saturating_add(bytes);
+    }
+}
+
+impl fmt::Display for Breakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (kind, bytes) in self.iter() {
+            writeln!(f, "{kind} {bytes}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Recorder {
+    breakdown: Breakdown,
+    // The number of bytes tagged by nested `kind_heap_size`.
+    tagged: usize,
+    // Elements of approximated collections are scaled up by their lengths.
+    scale: usize,
+}
+
+thread_local! {
+    static RECORDER: RefCell<Option<Recorder>> = const { RefCell::new(None) };
+}
+
+/// Run `f` which returns a heap size, and break the heap size down by
+/// [`MemoryKind`].
+pub fn breakdown(f: impl FnOnce() -> usize) -> Breakdown {
+    // Restore the outer breakdown even if `f` panics.
+    struct Restore(Option<Recorder>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            RECORDER.with(|r| *r.borrow_mut() = self.0.take());
+        }
+    }
+    let outer = RECORDER.with(|r| {
+        r.borrow_mut().replace(Recorder {
+            breakdown: Breakdown::default(),
+            tagged: 0,
+            scale: 1,
+        })
+    });
+    let restore = Restore(outer);
+    let installed = Installed::new();
+    let bytes = f();
+    drop(installed);
+    let (mut breakdown, tagged) = RECORDER
+        .with(|r| r.borrow_mut().take())
+        .map_or((Breakdown::default(), 0), |r| (r.breakdown, r.tagged));
+    drop(restore);
+
+    breakdown.add(MemoryKind::Heap, bytes.saturating_sub(tagged));
+    breakdown
+}
+
+/// Return the heap size returned by `f`, and tag it as `kind`.
+///
+/// Bytes tagged by nested calls keep their own kinds.
+#[allow(clippy::module_name_repetitions)]
+pub fn kind_heap_size(kind: MemoryKind, f: impl FnOnce() -> usize) -> usize {
+    if !any_installed() {
+        return f();
+    }
+    let Some(before) = RECORDER.with(|r| r.borrow().as_ref().map(|r| r.tagged)) else {
+        return f();
+    };
+    let bytes = f();
+    RECORDER.with(|r| {
+        if let Some(r) = r.borrow_mut().as_mut() {
+            let nested = r.tagged - before;
+            let own = bytes.saturating_sub(nested / r.scale);
+            r.breakdown.add(kind, own.saturating_mul(r.scale));
+            r.tagged = before + nested.max(bytes.saturating_mul(r.scale));
+        }
+    });
+    bytes
+}
+
+/// Return the heap size returned by `f`, and tag all of it as `kind`.
+///
+/// Unlike [`kind_heap_size`], bytes tagged by nested calls are tagged as
+/// `kind` too, e.g., a `bytes::Bytes` pointing to static data is shared, but
+/// can be tagged as static here. It is used by `#[heap_size(kind = "...")]`.
+pub fn override_kind_heap_size(kind: MemoryKind, f: impl FnOnce() -> usize) -> usize {
+    if !any_installed() {
+        return f();
+    }
+    let Some((before, breakdown)) =
+        RECORDER.with(|r| r.borrow().as_ref().map(|r| (r.tagged, r.breakdown)))
+    else {
+        return f();
+    };
+    let bytes = f();
+    RECORDER.with(|r| {
+        if let Some(r) = r.borrow_mut().as_mut() {
+            // Drop nested tags, and tag every byte as `kind`.
+            let bytes = bytes.saturating_mul(r.scale);
+            r.breakdown = breakdown;
+            r.breakdown.add(kind, bytes);
+            r.tagged = before.saturating_add(bytes);
+        }
+    });
+    bytes
+}
+
+/// Run `f` which computes the heap size of one element on behalf of `len`
+/// elements, so that tagged bytes of the element are scaled up.
+pub(crate) fn scaled<R>(len: usize, f: impl FnOnce() -> R) -> R {
+    if !any_installed() {
+        return f();
+    }
+    let Some(prev) = RECORDER.with(|r| {
+        let mut r = r.borrow_mut();
+        let r = r.as_mut()?;
+        let scale = r.scale.saturating_mul(len);
+        Some(std::mem::replace(&mut r.scale, scale))
+    }) else {
+        return f();
+    };
+    let res = f();
+    RECORDER.with(|r| {
+        if let Some(r) = r.borrow_mut().as_mut() {
+            r.scale = prev;
+        }
+    });
+    res
+}
+
+#[test]
+fn test_breakdown() {
+    use std::{rc::Rc, sync::Arc};
+
+    use crate::HeapSize;
+
+    let shared = Arc::new(vec![0u8; 8]);
+    let v = vec![shared.clone(), shared.clone()];
+    let cap_bytes = v.capacity() * std::mem::size_of::<Arc<Vec<u8>>>();
+    let breakdown = v.heap_size_by_kind();
+    assert_eq!(cap_bytes, breakdown.get(MemoryKind::Heap));
+    assert_eq!(2 * shared.heap_size(), breakdown.get(MemoryKind::Shared));
+    assert_eq!(v.heap_size(), breakdown.total());
+
+    // Nested kinds keep their own kinds.
+    let rc = Rc::new(std::io::BufReader::with_capacity(16, std::io::empty()));
+    let breakdown = rc.heap_size_by_kind();
+    assert_eq!(16, breakdown.get(MemoryKind::Pinned));
+    assert_eq!(rc.heap_size() - 16, breakdown.get(MemoryKind::Shared));
+    assert_eq!(0, breakdown.get(MemoryKind::Heap));
+
+    let mmap = breakdown_of_mmap();
+    assert_eq!(64, mmap.get(MemoryKind::Mmap));
+    assert_eq!(
+        "heap 0\nmmap 64\nshared 0\nstatic 0\npinned 0\n",
+        mmap.to_string()
+    );
+}
+
+#[test]
+fn test_override_kind() {
+    use std::sync::Arc;
+
+    use crate::HeapSize;
+
+    let arc = Arc::new(vec![0u8; 8]);
+    let v = vec![arc.clone(), arc.clone()];
+    let breakdown = breakdown(|| {
+        override_kind_heap_size(MemoryKind::Static, || {
+            kind_heap_size(MemoryKind::Pinned, || 11)
+        }) + override_kind_heap_size(MemoryKind::Mmap, || v.heap_size())
+    });
+    assert_eq!(11, breakdown.get(MemoryKind::Static));
+    assert_eq!(v.heap_size(), breakdown.get(MemoryKind::Mmap));
+    assert_eq!(0, breakdown.get(MemoryKind::Pinned));
+    assert_eq!(0, breakdown.get(MemoryKind::Shared));
+    assert_eq!(0, breakdown.get(MemoryKind::Heap));
+}
+
+#[cfg(test)]
+fn breakdown_of_mmap() -> Breakdown {
+    breakdown(|| kind_heap_size(MemoryKind::Mmap, || 64))
+}
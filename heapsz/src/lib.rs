@@ -15,6 +15,89 @@ pub trait HeapSize {
     {
         core::mem::size_of::<Self>() + self.heap_size()
     }
+
+    /// Return the number of bytes it owns on the heap, with bounds.
+    ///
+    /// See [`estimate`] for details.
+    fn heap_size_estimate(&self) -> estimate::Estimate {
+        estimate::estimate(|| self.heap_size())
+    }
+
+    /// Return the number of bytes it owns on the heap, broken down by
+    /// [`kind::MemoryKind`].
+    ///
+    /// See [`kind`] for details.
+    fn heap_size_by_kind(&self) -> kind::Breakdown {
+        kind::breakdown(|| self.heap_size())
+    }
+
+    /// Return the number of bytes it owns on the heap, counting large buffers
+    /// by their resident pages.
+    ///
+    /// See [`resident`] for details.
+    fn resident_heap_size(&self) -> usize {
+        resident::resident(|| self.heap_size())
+    }
+
+    /// Return the number of bytes it owns on the heap, measured by the
+    /// usable sizes reported by `backend`.
+    ///
+    /// See [`measure`] for details.
+    ///
+    /// # Safety
+    ///
+    /// Every allocation it owns must be made by `backend`, see
+    /// [`measure::measured`].
+    unsafe fn measured_heap_size(&self, backend: &'static dyn measure::Backend) -> usize {
+        measure::measured(backend, || self.heap_size())
+    }
+}
+
+/// Query the number of bytes of the allocation directly owned by an object.
+///
+/// Unlike [`HeapSize`], it does not count heap memory owned by elements,
+/// e.g., `Vec<String>` only counts its own buffer, not the strings. So it does
+/// not require elements to implement `HeapSize`.
+///
+/// It can be used by `#[derive(HeapSize)]` with `#[heap_size(shallow)]`.
+pub trait ShallowHeapSize {
+    /// Return the number of bytes it directly owns on the heap.
+    fn shallow_heap_size(&self) -> usize;
 }
 
+pub mod alloc;
+pub mod context;
+pub mod drift;
+pub mod estimate;
+mod helpers;
 mod impls;
+pub mod kind;
+pub mod measure;
+mod modes;
+#[cfg(target_os = "linux")]
+pub mod procfs;
+pub mod reconcile;
+pub mod resident;
+pub mod retained;
+pub mod traverse;
+
+pub use alloc::scope;
+
+pub use helpers::{
+    contiguous_heap_size, contiguous_heap_size_at, field_heap_size, node_based_heap_size,
+    owned_slice_heap_size,
+};
+pub use traverse::elements_heap_size;
+/// The derive macro, in its own module so that `heapsz::HeapSize` only names
+/// the trait, and `use heapsz_derive::HeapSize;` keeps working next to it.
+#[cfg(feature = "derive")]
+pub mod derive {
+    /// Derive `HeapSize`, see the crate documentation for attributes.
+    pub use heapsz_derive::HeapSize;
+}
+#[cfg(feature = "jemalloc")]
+pub mod jemalloc;
+#[cfg(feature = "rayon")]
+pub mod par;
+#[cfg(feature = "rayon")]
+pub use par::ParHeapSize;
@@ -0,0 +1,192 @@
+//! Measure allocations by asking the allocator.
+//!
+//! By default, an allocation is counted by the number of bytes requested,
+//! e.g., `capacity()` of a `Vec`. Allocators round requests up to size
+//! classes, so the real number of bytes is larger. Within [`measured`], the
+//! built-in implementations ask a [`Backend`] for the usable size of every
+//! owned allocation instead, like Firefox's `malloc_size_of`.
+//!
+//! Backends are enabled by features:
+//!
+//! * `malloc_usable_size`: [`Glibc`], `malloc_usable_size` of glibc.
+//! * `sallocx`: [`Jemalloc`], `sallocx` of jemalloc.
+//! * `mi_usable_size`: [`Mimalloc`], `mi_usable_size` of mimalloc.
+//!
+//! `Vec`, `String`, `Box`, `Rc`, `Arc`, `VecDeque` and `BinaryHeap` buffers
+//! are measured.
+//! `bytes::Bytes` and `bytes::BytesMut` may not start at their allocations,
+//! and passing any other pointer to a backend is undefined behavior, so they
+//! are still counted by their lengths and capacities.
+
+use std::{
+    cell::Cell,
+    mem::{align_of_val, size_of},
+};
+
+use crate::modes::{any_installed, Installed};
+
+/// An allocator that reports the usable size of its allocations.
+pub trait Backend: Sync {
+    /// Return the number of usable bytes of the allocation starting at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be the start of a live allocation made by this allocator.
+    unsafe fn usable_size(&self, ptr: *const u8) -> usize;
+}
+
+thread_local! {
+    static BACKEND: Cell<Option<&'static dyn Backend>> = const { Cell::new(None) };
+}
+
+/// Run `f` which returns a heap size, measuring owned allocations by
+/// `backend`.
+///
+/// # Safety
+///
+/// Every allocation reached by `f` must be made by `backend`, i.e., it must
+/// be the global allocator.
+pub unsafe fn measured(backend: &'static dyn Backend, f: impl FnOnce() -> usize) -> usize {
+    // Restore the previous backend even if `f` panics.
+    struct Restore(Option<&'static dyn Backend>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            BACKEND.with(|b| b.set(self.0));
+        }
+    }
+    let prev = BACKEND.with(|b| b.replace(Some(backend)));
+    let _restore = Restore(prev);
+    let _installed = Installed::new();
+    f()
+}
+
+/// Whether a [`measured`] is running on the current thread.
+pub fn is_measuring() -> bool {
+    BACKEND.with(Cell::get).is_some()
+}
+
+/// Return the number of bytes of an allocation of `bytes` bytes starting at
+/// `ptr`.
+///
+/// Within [`measured`], it is the usable size reported by the backend.
+/// Otherwise, or if nothing is allocated, it returns `bytes`.
+pub fn measured_bytes<T: ?Sized>(ptr: *const T, bytes: usize) -> usize {
+    if bytes == 0 || !any_installed() {
+        return bytes;
+    }
+    match BACKEND.with(Cell::get) {
+        // SAFETY: the caller of `measured` guarantees the allocation is made
+        // by the backend.
+        Some(backend) => unsafe { backend.usable_size(ptr.cast::<u8>()) },
+        None => bytes,
+    }
+}
+
+/// Return the start of the allocation of an `Rc` or `Arc` pointing to
+/// `value`, where the strong and the weak counts precede the value.
+pub(crate) fn counted_alloc<T: ?Sized>(value: &T) -> *const u8 {
+    let offset = (2 * size_of::<usize>()).next_multiple_of(align_of_val(value));
+    std::ptr::from_ref(value).cast::<u8>().wrapping_sub(offset)
+}
+
+/// `malloc_usable_size` of glibc.
+#[cfg(all(feature = "malloc_usable_size", target_os = "linux"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Glibc;
+
+#[cfg(all(feature = "malloc_usable_size", target_os = "linux"))]
+impl Backend for Glibc {
+    unsafe fn usable_size(&self, ptr: *const u8) -> usize {
+        libc::malloc_usable_size(ptr as *mut core::ffi::c_void)
+    }
+}
+
+/// `sallocx` of jemalloc.
+#[cfg(feature = "sallocx")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Jemalloc;
+
+#[cfg(feature = "sallocx")]
+impl Backend for Jemalloc {
+    unsafe fn usable_size(&self, ptr: *const u8) -> usize {
+        tikv_jemalloc_sys::sallocx(ptr.cast::<core::ffi::c_void>(), 0)
+    }
+}
+
+/// `mi_usable_size` of mimalloc.
+#[cfg(feature = "mi_usable_size")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mimalloc;
+
+#[cfg(feature = "mi_usable_size")]
+impl Backend for Mimalloc {
+    unsafe fn usable_size(&self, ptr: *const u8) -> usize {
+        libmimalloc_sys::mi_usable_size(ptr.cast::<core::ffi::c_void>())
+    }
+}
+
+#[test]
+fn test_measured() {
+    use std::{rc::Rc, sync::Arc};
+
+    use crate::HeapSize;
+
+    // Every allocation is 64 bytes.
+    struct Fixed;
+    impl Backend for Fixed {
+        unsafe fn usable_size(&self, _: *const u8) -> usize {
+            64
+        }
+    }
+
+    let v = vec![vec![0u8; 1]; 2];
+    assert_eq!(64 * 3, unsafe { v.measured_heap_size(&Fixed) });
+    assert!(!is_measuring());
+    let s = String::with_capacity(4);
+    assert_eq!(64, unsafe { s.measured_heap_size(&Fixed) });
+    let empty: Vec<u8> = Vec::new();
+    assert_eq!(0, unsafe { empty.measured_heap_size(&Fixed) });
+    let b = Box::new(vec![0u8; 1]);
+    assert_eq!(128, unsafe { b.measured_heap_size(&Fixed) });
+    let rc = Rc::new(1u64);
+    assert_eq!(64, unsafe { rc.measured_heap_size(&Fixed) });
+    let arc: Arc<[u8]> = Arc::from(vec![0u8; 4]);
+    assert_eq!(64, unsafe { arc.measured_heap_size(&Fixed) });
+
+    // Deques are measured whether they wrap or not.
+    let mut d = std::collections::VecDeque::<u8>::with_capacity(4);
+    assert_eq!(64, unsafe { d.measured_heap_size(&Fixed) });
+    d.extend([1, 2, 3, 4]);
+    d.rotate_left(2);
+    assert_eq!(64, unsafe { d.measured_heap_size(&Fixed) });
+}
+
+#[cfg(all(test, feature = "malloc_usable_size", target_os = "linux"))]
+#[test]
+fn test_glibc() {
+    use crate::HeapSize;
+
+    let v = vec![0u8; 100];
+    let measured = unsafe { v.measured_heap_size(&Glibc) };
+    assert!(measured >= 100, "{}", measured);
+}
+
+#[cfg(all(test, feature = "sallocx"))]
+#[test]
+fn test_jemalloc() {
+    unsafe {
+        let ptr = tikv_jemalloc_sys::mallocx(100, 0);
+        assert!(Jemalloc.usable_size(ptr as *const u8) >= 100);
+        tikv_jemalloc_sys::sdallocx(ptr, 100, 0);
+    }
+}
+
+#[cfg(all(test, feature = "mi_usable_size"))]
+#[test]
+fn test_mimalloc() {
+    unsafe {
+        let ptr = libmimalloc_sys::mi_malloc(100);
+        assert!(Mimalloc.usable_size(ptr as *const u8) >= 100);
+        libmimalloc_sys::mi_free(ptr);
+    }
+}
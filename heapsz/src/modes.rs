@@ -0,0 +1,50 @@
+//! Bookkeeping of thread-local modes, e.g., a traversal, an estimate or a
+//! retained size graph.
+//!
+//! Every mode lives in a thread local, and looking all of them up costs more
+//! than the O(1) approximation itself. So installed modes are also counted
+//! process-wide, hot paths skip the lookups while none is installed.
+
+use std::{
+    cell::Cell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static INSTALLED: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static INSTALLED_HERE: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Whether any mode is installed on any thread.
+///
+/// A mode is installed and looked up on the same thread, so a relaxed load
+/// always observes modes of the current thread.
+#[inline]
+pub(crate) fn any_installed() -> bool {
+    INSTALLED.load(Ordering::Relaxed) != 0
+}
+
+/// Whether any mode is installed on the current thread.
+#[cfg(feature = "rayon")]
+pub(crate) fn installed_here() -> bool {
+    any_installed() && INSTALLED_HERE.with(Cell::get) != 0
+}
+
+/// A guard of an installed mode, which uninstalls it once dropped.
+pub(crate) struct Installed(());
+
+impl Installed {
+    pub(crate) fn new() -> Self {
+        INSTALLED.fetch_add(1, Ordering::Relaxed);
+        INSTALLED_HERE.with(|n| n.set(n.get() + 1));
+        Installed(())
+    }
+}
+
+impl Drop for Installed {
+    fn drop(&mut self) {
+        INSTALLED_HERE.with(|n| n.set(n.get() - 1));
+        INSTALLED.fetch_sub(1, Ordering::Relaxed);
+    }
+}
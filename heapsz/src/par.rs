@@ -0,0 +1,194 @@
+//! Parallel heap size traversal, powered by [`rayon`].
+//!
+//! Thread-local modes, e.g., a [`Traversal`](crate::traverse::Traversal) or
+//! an [`estimate`](crate::estimate::estimate), are not carried onto rayon
+//! worker threads. While any of them is installed on the current thread,
+//! elements are visited on the current thread instead, so that the mode
+//! still applies.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    hash::{BuildHasher, Hash},
+    mem::size_of,
+};
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{modes::installed_here, HeapSize};
+
+/// Collections that have more elements than `PAR_THRESHOLD` are split across
+/// the rayon thread pool, smaller ones are traversed on the current thread.
+pub const PAR_THRESHOLD: usize = 4096;
+
+/// Query the number of bytes of an object, visiting every element in
+/// parallel.
+///
+/// Unlike [`HeapSize::heap_size`], which approximates a collection by its
+/// first element in O(1) time, `par_heap_size` sums up `heap_size()` of every
+/// element in O(n) time. Collections nested in elements are still
+/// approximated by `heap_size()`, run it in a
+/// [`Traversal`](crate::traverse::Traversal) to count them exactly.
+///
+/// It can be generated by `#[derive(HeapSize)]` with `#[heap_size(par)]`
+/// fields, which are traversed concurrently by [`rayon::join`].
+#[allow(clippy::module_name_repetitions)]
+pub trait ParHeapSize: HeapSize {
+    /// Return the number of bytes it owns on the heap.
+    fn par_heap_size(&self) -> usize;
+}
+
+/// Run `a` and `b` concurrently by [`rayon::join`], or one after another if
+/// any thread-local mode is installed on the current thread.
+///
+/// It is called by `#[derive(HeapSize)]`.
+#[doc(hidden)]
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    if installed_here() {
+        (a(), b())
+    } else {
+        rayon::join(a, b)
+    }
+}
+
+fn sum_heap_size<'a, C, I, F>(len: usize, collection: &'a C, f: F) -> usize
+where
+    C: ?Sized,
+    &'a C: IntoParallelIterator<Item = I> + IntoIterator<Item = I>,
+    F: Fn(I) -> usize + Sync + Send,
+{
+    if len > PAR_THRESHOLD && !installed_here() {
+        collection.into_par_iter().map(f).sum()
+    } else {
+        IntoIterator::into_iter(collection).map(f).sum()
+    }
+}
+
+impl<T: HeapSize + Sync> ParHeapSize for Vec<T> {
+    fn par_heap_size(&self) -> usize {
+        let cap_bytes = self.capacity() * size_of::<T>();
+        cap_bytes + sum_heap_size(self.len(), self, T::heap_size)
+    }
+}
+
+impl<T: HeapSize + Sync> ParHeapSize for Box<[T]> {
+    fn par_heap_size(&self) -> usize {
+        let bytes = self.len() * size_of::<T>();
+        bytes + sum_heap_size(self.len(), &**self, T::heap_size)
+    }
+}
+
+impl<T: HeapSize + Sync> ParHeapSize for VecDeque<T> {
+    fn par_heap_size(&self) -> usize {
+        let cap_bytes = self.capacity() * size_of::<T>();
+        cap_bytes + sum_heap_size(self.len(), self, T::heap_size)
+    }
+}
+
+impl<K, V, S> ParHeapSize for HashMap<K, V, S>
+where
+    K: HeapSize + Hash + Eq + Sync,
+    V: HeapSize + Sync,
+    S: BuildHasher,
+{
+    fn par_heap_size(&self) -> usize {
+        let cap_bytes = self.capacity() * (size_of::<K>() + size_of::<V>());
+        cap_bytes + sum_heap_size(self.len(), self, |(k, v)| k.heap_size() + v.heap_size())
+    }
+}
+
+impl<T, S> ParHeapSize for HashSet<T, S>
+where
+    T: HeapSize + Hash + Eq + Sync,
+    S: BuildHasher,
+{
+    fn par_heap_size(&self) -> usize {
+        let cap_bytes = self.capacity() * size_of::<T>();
+        cap_bytes + sum_heap_size(self.len(), self, T::heap_size)
+    }
+}
+
+impl<K, V> ParHeapSize for BTreeMap<K, V>
+where
+    K: HeapSize + Ord + Sync,
+    V: HeapSize + Sync,
+{
+    fn par_heap_size(&self) -> usize {
+        sum_heap_size(self.len(), self, |(k, v)| k.memory_size() + v.memory_size())
+    }
+}
+
+#[test]
+fn test_vec() {
+    let mut v: Vec<Vec<u8>> = Vec::with_capacity(PAR_THRESHOLD * 2);
+    assert_eq!(v.heap_size(), v.par_heap_size());
+    for i in 0..PAR_THRESHOLD * 2 {
+        v.push(Vec::with_capacity(i % 7));
+    }
+    let exact = v.capacity() * size_of::<Vec<u8>>() + v.iter().map(|v| v.capacity()).sum::<usize>();
+    assert_eq!(exact, v.par_heap_size());
+
+    let b = v.into_boxed_slice();
+    let exact = b.len() * size_of::<Vec<u8>>() + b.iter().map(|v| v.capacity()).sum::<usize>();
+    assert_eq!(exact, b.par_heap_size());
+}
+
+#[test]
+fn test_vec_deque() {
+    let mut v: VecDeque<String> = VecDeque::new();
+    for i in 0..PAR_THRESHOLD * 2 {
+        v.push_back("x".repeat(i % 5));
+    }
+    let exact = v.capacity() * size_of::<String>() + v.iter().map(|s| s.capacity()).sum::<usize>();
+    assert_eq!(exact, v.par_heap_size());
+}
+
+#[test]
+fn test_modes() {
+    use crate::traverse::Traversal;
+
+    let v: Vec<Vec<Vec<u8>>> = (0..PAR_THRESHOLD * 2)
+        .map(|i| (0..i % 5).map(|j| vec![0; j]).collect())
+        .collect();
+    // Nested collections are approximated, unless in a traversal.
+    let approx =
+        v.capacity() * size_of::<Vec<Vec<u8>>>() + v.iter().map(|v| v.heap_size()).sum::<usize>();
+    assert_eq!(approx, v.par_heap_size());
+    assert_eq!(
+        Traversal::new().run(&v).heap_size,
+        Traversal::new().run_with(|| v.par_heap_size()).heap_size
+    );
+    assert_ne!(approx, Traversal::new().run(&v).heap_size);
+}
+
+#[test]
+fn test_maps() {
+    let mut map = HashMap::new();
+    let mut set = HashSet::new();
+    let mut btree = BTreeMap::new();
+    for i in 0..PAR_THRESHOLD * 2 {
+        map.insert(i, vec![0u8; i % 3]);
+        set.insert(i.to_string());
+        btree.insert(i, vec![0u8; i % 3]);
+    }
+    let values = map.values().map(|v| v.capacity()).sum::<usize>();
+    assert_eq!(
+        map.capacity() * (size_of::<usize>() + size_of::<Vec<u8>>()) + values,
+        map.par_heap_size()
+    );
+    let keys = set.iter().map(|v| v.capacity()).sum::<usize>();
+    assert_eq!(
+        set.capacity() * size_of::<String>() + keys,
+        set.par_heap_size()
+    );
+    let values = btree.values().map(|v| v.capacity()).sum::<usize>();
+    assert_eq!(
+        btree.len() * (size_of::<usize>() + size_of::<Vec<u8>>()) + values,
+        btree.par_heap_size()
+    );
+}
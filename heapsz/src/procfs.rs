@@ -0,0 +1,353 @@
+//! Reconcile `HeapSize` accounting against `/proc` on Linux.
+//!
+//! Unlike `heapsz::jemalloc`, it works with any allocator: [`Memory::read`]
+//! parses `/proc/self/smaps_rollup`, `/proc/self/status` or
+//! `/proc/self/statm`, whichever is available first, into RSS, anonymous,
+//! file-backed and swap figures. Heap memory is anonymous, so [`reconcile()`]
+//! reports anonymous bytes that no structure explains as an `unaccounted`
+//! line item.
+//!
+//! ```no_run
+//! use heapsz::{procfs, HeapSize};
+//!
+//! let cache = vec![0u8; 1024];
+//! let reconciliation = procfs::reconcile([("cache", cache.heap_size())]).unwrap();
+//! println!("{reconciliation}");
+//! ```
+//!
+//! Files are read by a [`ProcFs`], which can be replaced by a fake one in
+//! tests.
+
+use std::{
+    fmt, fs,
+    io::{self, ErrorKind},
+    path::PathBuf,
+};
+
+use crate::reconcile;
+pub use crate::reconcile::Item;
+
+/// A source of `/proc/self` files.
+pub trait ProcFs {
+    /// Return the content of the file `name`, e.g., `status`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can not be read.
+    fn read(&self, name: &str) -> io::Result<String>;
+
+    /// Return the page size in bytes, which is the unit of `statm`.
+    fn page_size(&self) -> usize {
+        // SAFETY: sysconf is always safe to call.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        usize::try_from(page_size).unwrap_or(4096)
+    }
+}
+
+/// Files in a directory, `/proc/self` by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Root {
+    path: PathBuf,
+}
+
+impl Root {
+    /// Read files in `path`, e.g., a directory of fixture files.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Root { path: path.into() }
+    }
+}
+
+impl Default for Root {
+    fn default() -> Self {
+        Root::new("/proc/self")
+    }
+}
+
+impl ProcFs for Root {
+    fn read(&self, name: &str) -> io::Result<String> {
+        fs::read_to_string(self.path.join(name))
+    }
+}
+
+/// Memory figures of a process, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Memory {
+    /// The resident set size.
+    pub rss: usize,
+    /// Resident anonymous memory, where the heap lives.
+    pub anon: usize,
+    /// Resident file-backed and shared memory.
+    pub file: usize,
+    /// Anonymous memory swapped out, 0 if it is unknown, e.g., read from
+    /// `statm`.
+    pub swap: usize,
+}
+
+impl Memory {
+    /// Read memory figures from `smaps_rollup`, `status` or `statm`,
+    /// whichever can be read first.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error if none of them can be read or parsed.
+    pub fn read(fs: &impl ProcFs) -> io::Result<Memory> {
+        let mut err = None;
+        match fs.read("smaps_rollup").and_then(|s| parse_smaps_rollup(&s)) {
+            Ok(memory) => return Ok(memory),
+            Err(e) => err = err.or(Some(e)),
+        }
+        match fs.read("status").and_then(|s| parse_status(&s)) {
+            Ok(memory) => return Ok(memory),
+            Err(e) => err = err.or(Some(e)),
+        }
+        match fs
+            .read("statm")
+            .and_then(|s| parse_statm(&s, fs.page_size()))
+        {
+            Ok(memory) => Ok(memory),
+            Err(e) => Err(err.unwrap_or(e)),
+        }
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+// Return values of `keys` in lines like `Rss:  1420 kB`, in bytes.
+fn parse_kb<const N: usize>(content: &str, keys: [&str; N]) -> io::Result<[usize; N]> {
+    let mut values = [None; N];
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let Some(i) = keys.iter().position(|k| *k == key) else {
+            continue;
+        };
+        let kb = value
+            .trim()
+            .strip_suffix("kB")
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .ok_or_else(|| invalid(format!("invalid value of `{key}`: {}", value.trim())))?;
+        values[i] = Some(kb.saturating_mul(1024));
+    }
+    let mut bytes = [0; N];
+    for (i, value) in values.into_iter().enumerate() {
+        bytes[i] = value.ok_or_else(|| invalid(format!("missing `{}`", keys[i])))?;
+    }
+    Ok(bytes)
+}
+
+/// Parse the content of `/proc/self/smaps_rollup`.
+///
+/// # Errors
+///
+/// Returns [`io::ErrorKind::InvalidData`] if a figure is missing or invalid.
+pub fn parse_smaps_rollup(content: &str) -> io::Result<Memory> {
+    let [rss, anon, swap] = parse_kb(content, ["Rss", "Anonymous", "Swap"])?;
+    Ok(Memory {
+        rss,
+        anon,
+        file: rss.saturating_sub(anon),
+        swap,
+    })
+}
+
+/// Parse the content of `/proc/self/status`.
+///
+/// # Errors
+///
+/// Returns [`io::ErrorKind::InvalidData`] if a figure is missing or invalid.
+pub fn parse_status(content: &str) -> io::Result<Memory> {
+    let [rss, anon, file, shmem, swap] = parse_kb(
+        content,
+        ["VmRSS", "RssAnon", "RssFile", "RssShmem", "VmSwap"],
+    )?;
+    Ok(Memory {
+        rss,
+        anon,
+        file: file + shmem,
+        swap,
+    })
+}
+
+/// Parse the content of `/proc/self/statm`, whose figures are in pages of
+/// `page_size` bytes.
+///
+/// # Errors
+///
+/// Returns [`io::ErrorKind::InvalidData`] if a figure is missing or invalid.
+pub fn parse_statm(content: &str, page_size: usize) -> io::Result<Memory> {
+    let mut fields = content.split_whitespace().map(|f| {
+        f.parse::<usize>()
+            .map_err(|_| invalid(format!("invalid statm field: {f}")))
+    });
+    let mut next = |name: &str| {
+        fields
+            .next()
+            .unwrap_or_else(|| Err(invalid(format!("missing statm field `{name}`"))))
+            .map(|pages| pages.saturating_mul(page_size))
+    };
+    let _size = next("size")?;
+    let rss = next("resident")?;
+    let file = next("shared")?;
+    Ok(Memory {
+        rss,
+        anon: rss.saturating_sub(file),
+        file,
+        swap: 0,
+    })
+}
+
+/// Accounted line items compared to memory figures of a process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reconciliation {
+    /// Memory figures of the process.
+    pub memory: Memory,
+    /// Accounted line items, in the order they are given.
+    pub items: Vec<Item>,
+    /// The total number of bytes of all items.
+    pub accounted: usize,
+    /// The number of anonymous bytes that no item accounts for, it is 0 if
+    /// items account for more than anonymous memory.
+    pub unaccounted: usize,
+}
+
+impl Reconciliation {
+    /// Compare `items`, pairs of names and numbers of bytes, to `memory`.
+    pub fn new<S: Into<String>>(
+        memory: Memory,
+        items: impl IntoIterator<Item = (S, usize)>,
+    ) -> Self {
+        let (items, accounted) = reconcile::collect(items);
+        Reconciliation {
+            memory,
+            items,
+            accounted,
+            unaccounted: memory.anon.saturating_sub(accounted),
+        }
+    }
+
+    /// Return the fraction of anonymous bytes explained by items.
+    #[must_use]
+    // A fraction does not need every bit of the sizes.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn explained(&self) -> f64 {
+        if self.memory.anon == 0 {
+            return 1.0;
+        }
+        self.accounted as f64 / self.memory.anon as f64
+    }
+}
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in &self.items {
+            writeln!(f, "{} {}", item.name, item.bytes)?;
+        }
+        writeln!(f, "unaccounted {}", self.unaccounted)?;
+        writeln!(f, "anon {}", self.memory.anon)?;
+        writeln!(f, "file {}", self.memory.file)?;
+        writeln!(f, "swap {}", self.memory.swap)?;
+        writeln!(f, "rss {}", self.memory.rss)
+    }
+}
+
+/// Read memory figures of the current process, and compare `items`, pairs of
+/// names and numbers of bytes, to them.
+///
+/// # Errors
+///
+/// Returns an error if memory figures can not be read, see [`Memory::read`].
+pub fn reconcile<S: Into<String>>(
+    items: impl IntoIterator<Item = (S, usize)>,
+) -> io::Result<Reconciliation> {
+    reconcile_in(&Root::default(), items)
+}
+
+/// Like [`reconcile()`], but read memory figures from `fs`.
+///
+/// # Errors
+///
+/// Returns an error if memory figures can not be read, see [`Memory::read`].
+pub fn reconcile_in<S: Into<String>>(
+    fs: &impl ProcFs,
+    items: impl IntoIterator<Item = (S, usize)>,
+) -> io::Result<Reconciliation> {
+    Ok(Reconciliation::new(Memory::read(fs)?, items))
+}
+
+#[cfg(test)]
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/proc");
+
+#[cfg(test)]
+const KB: usize = 1024;
+
+#[test]
+fn test_parse_fixtures() {
+    let read = |name: &str| Root::new(FIXTURES).read(name).unwrap();
+    let expected = Memory {
+        rss: 524288 * KB,
+        anon: 409600 * KB,
+        file: 114688 * KB,
+        swap: 2048 * KB,
+    };
+    assert_eq!(expected, parse_smaps_rollup(&read("smaps_rollup")).unwrap());
+    assert_eq!(expected, parse_status(&read("status")).unwrap());
+    assert_eq!(
+        Memory {
+            swap: 0,
+            ..expected
+        },
+        parse_statm(&read("statm"), 4096).unwrap()
+    );
+
+    assert!(parse_status("VmRSS: 1 kB\n").is_err());
+    assert!(parse_smaps_rollup("Rss: x kB\nAnonymous: 0 kB\nSwap: 0 kB\n").is_err());
+    assert!(parse_statm("1", 4096).is_err());
+}
+
+#[test]
+fn test_reconcile_fake() {
+    use std::collections::HashMap;
+
+    // Only `statm` is available.
+    struct Fake(HashMap<&'static str, &'static str>);
+    impl ProcFs for Fake {
+        fn read(&self, name: &str) -> io::Result<String> {
+            self.0
+                .get(name)
+                .map(|s| s.to_string())
+                .ok_or_else(|| io::Error::from(ErrorKind::NotFound))
+        }
+        fn page_size(&self) -> usize {
+            4096
+        }
+    }
+    let fs = Fake(HashMap::from([("statm", "10 5 2 1 0 3 0\n")]));
+    let r = reconcile_in(&fs, [("cache", 4096)]).unwrap();
+    assert_eq!(3 * 4096, r.memory.anon);
+    assert_eq!(2 * 4096, r.unaccounted);
+    assert_eq!(
+        "cache 4096\nunaccounted 8192\nanon 12288\nfile 8192\nswap 0\nrss 20480\n",
+        r.to_string()
+    );
+
+    let empty = Fake(HashMap::new());
+    assert_eq!(
+        ErrorKind::NotFound,
+        Memory::read(&empty).unwrap_err().kind()
+    );
+
+    let r = reconcile_in(&Root::new(FIXTURES), [("cache", 409600 * KB)]).unwrap();
+    assert_eq!(0, r.unaccounted);
+    assert!((r.explained() - 1.0).abs() < f64::EPSILON);
+}
+
+#[test]
+fn test_reconcile() {
+    let v = vec![1u8; 1 << 20];
+    let r = reconcile([("v", crate::HeapSize::heap_size(&v))]).unwrap();
+    assert!(r.memory.rss >= r.memory.anon);
+    assert_eq!(1 << 20, r.accounted);
+}
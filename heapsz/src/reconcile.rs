@@ -0,0 +1,31 @@
+//! Line items of reconciliation reports.
+//!
+//! Reports compare heap sizes of annotated structures to process-level
+//! figures, e.g., `heapsz::jemalloc` (the `jemalloc` feature) and
+//! `heapsz::procfs` (Linux).
+
+/// An accounted line item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    /// The name of the item, e.g., `block_cache`.
+    pub name: String,
+    /// The number of bytes it accounts for.
+    pub bytes: usize,
+}
+
+/// Collect `items`, pairs of names and numbers of bytes, and their total.
+pub(crate) fn collect<S: Into<String>>(
+    items: impl IntoIterator<Item = (S, usize)>,
+) -> (Vec<Item>, usize) {
+    let items: Vec<_> = items
+        .into_iter()
+        .map(|(name, bytes)| Item {
+            name: name.into(),
+            bytes,
+        })
+        .collect();
+    let total = items
+        .iter()
+        .fold(0usize, |acc, item| acc.saturating_add(item.bytes));
+    (items, total)
+}
@@ -0,0 +1,168 @@
+//! Count resident pages of large buffers.
+//!
+//! `Vec::with_capacity(1 << 30)` owns 1 GiB, but only pages that have been
+//! touched are resident. [`HeapSize::resident_heap_size`] counts buffers at
+//! least [`THRESHOLD`] bytes by their resident pages, which are queried by
+//! `mincore(2)` on Linux. Smaller buffers, and buffers on other platforms,
+//! are counted by their capacity.
+//!
+//! ```
+//! use heapsz::HeapSize;
+//!
+//! let mut v: Vec<u8> = Vec::with_capacity(64 << 20);
+//! v.extend_from_slice(&[1; 4096]);
+//! assert!(v.resident_heap_size() <= v.heap_size());
+//! ```
+//!
+//! [`HeapSize::resident_heap_size`]: crate::HeapSize::resident_heap_size
+
+use std::cell::Cell;
+
+use crate::modes::{any_installed, Installed};
+
+/// The minimum number of bytes of a buffer whose resident pages are queried.
+///
+/// It is the default mmap threshold of glibc malloc, smaller allocations are
+/// carved from arenas and are likely resident.
+pub const THRESHOLD: usize = 128 * 1024;
+
+thread_local! {
+    static RESIDENT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `f` which returns a heap size, counting large buffers by their resident
+/// pages.
+pub fn resident(f: impl FnOnce() -> usize) -> usize {
+    // Restore the previous mode even if `f` panics.
+    struct Restore(bool);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            RESIDENT.with(|r| r.set(self.0));
+        }
+    }
+    let prev = RESIDENT.with(|r| r.replace(true));
+    let _restore = Restore(prev);
+    let _installed = Installed::new();
+    f()
+}
+
+/// Whether a [`resident`] is running on the current thread.
+#[allow(clippy::module_name_repetitions)]
+pub fn is_resident() -> bool {
+    RESIDENT.with(Cell::get)
+}
+
+/// Return the number of bytes of a buffer of `bytes` bytes starting at `ptr`.
+///
+/// Within [`resident`], a buffer of at least [`THRESHOLD`] bytes is counted
+/// by its resident pages. Otherwise, it returns `bytes`.
+#[allow(clippy::module_name_repetitions)]
+pub fn resident_bytes<T: ?Sized>(ptr: *const T, bytes: usize) -> usize {
+    if bytes < THRESHOLD || !any_installed() || !is_resident() {
+        return bytes;
+    }
+    sys::resident_bytes(ptr.cast::<u8>() as usize, bytes).unwrap_or(bytes)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::sync::OnceLock;
+
+    // The number of pages queried by one `mincore` call.
+    const PAGES_PER_CALL: usize = 4096;
+
+    fn page_size() -> usize {
+        static PAGE_SIZE: OnceLock<usize> = OnceLock::new();
+        *PAGE_SIZE.get_or_init(|| {
+            // SAFETY: sysconf is always safe to call.
+            let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+            usize::try_from(page_size).unwrap_or(4096)
+        })
+    }
+
+    pub(super) fn resident_bytes(addr: usize, bytes: usize) -> Option<usize> {
+        let page_size = page_size();
+        let end = addr.checked_add(bytes)?;
+        let mut page = addr & !(page_size - 1);
+        let mut resident = 0;
+        let mut vec = [0u8; PAGES_PER_CALL];
+        while page < end {
+            let pages = ((end - page).div_ceil(page_size)).min(PAGES_PER_CALL);
+            // SAFETY: `page` is page aligned, and the range belongs to a live
+            // allocation, `vec` has room for `pages` bytes.
+            let ret = unsafe {
+                libc::mincore(
+                    page as *mut libc::c_void,
+                    pages * page_size,
+                    vec.as_mut_ptr().cast(),
+                )
+            };
+            if ret != 0 {
+                return None;
+            }
+            for (i, v) in vec[..pages].iter().enumerate() {
+                if v & 1 != 0 {
+                    // Only count the part of the page within the buffer.
+                    let start = (page + i * page_size).max(addr);
+                    let stop = (page + (i + 1) * page_size).min(end);
+                    resident += stop - start;
+                }
+            }
+            page += pages * page_size;
+        }
+        Some(resident)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    pub(super) fn resident_bytes(_addr: usize, _bytes: usize) -> Option<usize> {
+        None
+    }
+}
+
+#[test]
+fn test_resident_bytes() {
+    use crate::HeapSize;
+
+    let small = vec![0u8; 16];
+    assert_eq!(16, resident(|| small.heap_size()));
+
+    let mut v: Vec<u8> = Vec::with_capacity(64 << 20);
+    assert_eq!(v.capacity(), v.heap_size());
+    assert!(!is_resident());
+    if cfg!(target_os = "linux") {
+        // Nothing has been touched yet.
+        let untouched = v.resident_heap_size();
+        assert!(untouched < v.capacity() / 2, "{}", untouched);
+        v.resize(32 << 20, 1);
+        let touched = v.resident_heap_size();
+        assert!(touched >= 32 << 20, "{}", touched);
+        assert!(touched <= v.capacity());
+    } else {
+        assert_eq!(v.capacity(), v.resident_heap_size());
+    }
+
+    // Touched buffers are resident.
+    let b = vec![1u8; 1 << 20].into_boxed_slice();
+    assert_eq!(b.heap_size(), b.resident_heap_size());
+    let s = "a".repeat(1 << 20);
+    assert_eq!(s.heap_size(), s.resident_heap_size());
+    let mut d = std::collections::VecDeque::from(vec![1u8; 1 << 20]);
+    d.rotate_left(1 << 19);
+    assert!(!d.as_slices().1.is_empty());
+    assert_eq!(d.heap_size(), d.resident_heap_size());
+
+    // A deque which does not wrap is counted by resident pages too.
+    let mut d: std::collections::VecDeque<u8> = std::collections::VecDeque::with_capacity(64 << 20);
+    if cfg!(target_os = "linux") {
+        let untouched = d.resident_heap_size();
+        assert!(untouched < d.capacity() / 2, "{}", untouched);
+        d.push_back(1);
+        d.pop_front();
+        let untouched = d.resident_heap_size();
+        assert!(untouched < d.capacity() / 2, "{}", untouched);
+    } else {
+        assert_eq!(d.capacity(), d.resident_heap_size());
+    }
+}
@@ -0,0 +1,431 @@
+//! Retained sizes of graphs built from `Rc` and `Arc`.
+//!
+//! [`HeapSize::heap_size`] charges a shared allocation to every owner, so
+//! owners of the same `Rc<T>` double count `T`. A [`Graph`] instead records
+//! which owners reach which shared allocations, and charges every allocation
+//! only to the owner that dominates it, like a heap analyzer does. The
+//! retained size of an owner is the number of bytes that would be freed if
+//! the owner were dropped.
+//!
+//! Roots and fields of `#[derive(HeapSize)]` types are owners.
+//!
+//! ```
+//! use std::rc::Rc;
+//!
+//! use heapsz::retained::Graph;
+//!
+//! let shared = Rc::new(vec![0u8; 64]);
+//! let a = vec![shared.clone()];
+//! let b = vec![shared];
+//!
+//! let mut graph = Graph::new();
+//! graph.add_root("a", &a);
+//! graph.add_root("b", &b);
+//! let report = graph.report();
+//! // Dropping `a` alone does not free the shared vector.
+//! assert_eq!(a.capacity() * 8, report.roots[0].retained);
+//! assert_eq!(b.capacity() * 8, report.roots[1].retained);
+//! ```
+
+use std::{cell::RefCell, collections::HashMap, fmt, mem};
+
+use crate::{
+    kind::{kind_heap_size, MemoryKind},
+    modes::{any_installed, Installed},
+    traverse::{indirect_heap_size, shared_bytes, Shared, Traversal},
+    HeapSize,
+};
+
+/// The retained size of an owner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The path of the owner, e.g., `root.field.field`.
+    pub path: String,
+    /// The number of bytes that would be freed if the owner were dropped.
+    pub retained: usize,
+}
+
+/// Retained sizes of roots and fields in a [`Graph`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Retained sizes of roots, in the order they are added.
+    pub roots: Vec<Entry>,
+    /// Retained sizes of fields, in the order they are discovered.
+    pub fields: Vec<Entry>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in self.roots.iter().chain(&self.fields) {
+            writeln!(f, "{} {}", entry.path, entry.retained)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Label {
+    Root(String),
+    Field(&'static str),
+    Shared,
+}
+
+#[derive(Debug)]
+struct Node {
+    label: Label,
+    // The node that discovers it first, used for naming.
+    parent: usize,
+    // The number of bytes owned by the node itself.
+    bytes: usize,
+    successors: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct State {
+    // The first node is a virtual node which owns every root.
+    nodes: Vec<Node>,
+    shared: HashMap<usize, usize>,
+    fields: HashMap<(usize, &'static str), usize>,
+    current: usize,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            nodes: vec![Node {
+                label: Label::Shared,
+                parent: 0,
+                bytes: 0,
+                successors: vec![],
+            }],
+            shared: HashMap::new(),
+            fields: HashMap::new(),
+            current: 0,
+        }
+    }
+}
+
+impl State {
+    fn add_node(&mut self, label: Label) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            label,
+            parent: self.current,
+            bytes: 0,
+            successors: vec![],
+        });
+        self.nodes[self.current].successors.push(id);
+        id
+    }
+}
+
+thread_local! {
+    static STATE: RefCell<Option<State>> = const { RefCell::new(None) };
+}
+
+/// A graph of owners and the shared allocations they reach.
+#[derive(Debug, Default)]
+pub struct Graph {
+    state: State,
+}
+
+impl Graph {
+    /// Create an empty graph.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Traverse `value` exactly and add it as a root named `name`.
+    pub fn add_root<T: HeapSize + ?Sized>(&mut self, name: impl Into<String>, value: &T) {
+        // Take the state back even if `heap_size` panics.
+        struct Uninstall<'a> {
+            state: &'a mut State,
+            prev: Option<State>,
+        }
+        impl Drop for Uninstall<'_> {
+            fn drop(&mut self) {
+                if let Some(state) = STATE.with(|s| s.replace(self.prev.take())) {
+                    *self.state = state;
+                }
+            }
+        }
+
+        self.state.current = 0;
+        let root = self.state.add_node(Label::Root(name.into()));
+        self.state.current = root;
+
+        let prev = STATE.with(|s| s.replace(Some(mem::take(&mut self.state))));
+        let uninstall = Uninstall {
+            state: &mut self.state,
+            prev,
+        };
+        let installed = Installed::new();
+        // Every allocation is charged fully, then to its dominator.
+        let bytes = Traversal::new().shared(Shared::Full).run(value).heap_size;
+        drop(installed);
+        drop(uninstall);
+        self.state.nodes[root].bytes += bytes;
+    }
+
+    /// Compute retained sizes of roots and fields.
+    #[must_use]
+    pub fn report(&self) -> Report {
+        let nodes = &self.state.nodes;
+        let (order, idoms) = dominators(nodes);
+
+        // A node always follows its immediate dominator in reverse postorder.
+        let mut retained: Vec<usize> = nodes.iter().map(|n| n.bytes).collect();
+        for &id in order.iter().skip(1).rev() {
+            retained[idoms[id]] += retained[id];
+        }
+
+        let mut paths: Vec<String> = Vec::with_capacity(nodes.len());
+        let mut report = Report::default();
+        for (id, node) in nodes.iter().enumerate() {
+            let path = match &node.label {
+                Label::Root(name) => name.clone(),
+                Label::Field(name) => format!("{}.{}", paths[node.parent], name),
+                Label::Shared if id == 0 => String::new(),
+                Label::Shared => paths[node.parent].clone(),
+            };
+            let entry = Entry {
+                path: path.clone(),
+                retained: retained[id],
+            };
+            match node.label {
+                Label::Root(_) => report.roots.push(entry),
+                Label::Field(_) => report.fields.push(entry),
+                Label::Shared => (),
+            }
+            paths.push(path);
+        }
+        report
+    }
+}
+
+// Return nodes in reverse postorder and their immediate dominators, see
+// "A Simple, Fast Dominance Algorithm" by Cooper, Harvey and Kennedy.
+fn dominators(nodes: &[Node]) -> (Vec<usize>, Vec<usize>) {
+    let mut postorder = Vec::with_capacity(nodes.len());
+    let mut visited = vec![false; nodes.len()];
+    let mut stack = vec![(0, 0)];
+    visited[0] = true;
+    while let Some((id, next)) = stack.last_mut() {
+        if let Some(&succ) = nodes[*id].successors.get(*next) {
+            *next += 1;
+            if !visited[succ] {
+                visited[succ] = true;
+                stack.push((succ, 0));
+            }
+        } else {
+            postorder.push(*id);
+            stack.pop();
+        }
+    }
+    let mut rank = vec![0; nodes.len()];
+    for (i, &id) in postorder.iter().enumerate() {
+        rank[id] = i;
+    }
+    let mut predecessors = vec![vec![]; nodes.len()];
+    for (id, node) in nodes.iter().enumerate() {
+        for &succ in &node.successors {
+            predecessors[succ].push(id);
+        }
+    }
+
+    let order: Vec<usize> = postorder.into_iter().rev().collect();
+    let mut idoms = vec![usize::MAX; nodes.len()];
+    idoms[0] = 0;
+    let intersect = |idoms: &[usize], mut a: usize, mut b: usize| {
+        while a != b {
+            while rank[a] < rank[b] {
+                a = idoms[a];
+            }
+            while rank[b] < rank[a] {
+                b = idoms[b];
+            }
+        }
+        a
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &id in order.iter().skip(1) {
+            let mut idom = usize::MAX;
+            for &pred in &predecessors[id] {
+                if idoms[pred] == usize::MAX {
+                    continue;
+                }
+                idom = if idom == usize::MAX {
+                    pred
+                } else {
+                    intersect(&idoms, pred, idom)
+                };
+            }
+            if idoms[id] != idom {
+                idoms[id] = idom;
+                changed = true;
+            }
+        }
+    }
+    (order, idoms)
+}
+
+// Visit a child owner `f` as a node of the graph, if a graph is being built
+// on the current thread. `node` returns the node of the owner, or `None` if
+// the owner has been visited.
+fn visit(node: impl FnOnce(&mut State) -> Option<usize>, f: impl FnOnce() -> usize) -> usize {
+    if !any_installed() {
+        return f();
+    }
+    let Some(enter) = STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let state = s.as_mut()?;
+        Some(node(state).map(|id| (mem::replace(&mut state.current, id), id)))
+    }) else {
+        return f();
+    };
+    let Some((parent, id)) = enter else {
+        return 0;
+    };
+    // Do not hold any state while visiting the owner, it may own other
+    // nodes.
+    let bytes = f();
+    STATE.with(|s| {
+        if let Some(state) = s.borrow_mut().as_mut() {
+            state.nodes[id].bytes += bytes;
+            state.current = parent;
+        }
+    });
+    // The bytes are owned by the node rather than the current one.
+    0
+}
+
+/// Return the heap size of a shared allocation of `pointee` owned by `owners`
+/// owners, where `f` returns the number of bytes of the allocation.
+///
+/// While building a [`Graph`], the allocation is charged to the graph rather
+/// than the caller, and it is visited only once. Otherwise, it is charged by
+/// the current [`Shared`] mode.
+///
+/// Cycles and the maximum depth are guarded by
+/// [`indirect_heap_size`]. The bytes are tagged as [`MemoryKind::Shared`].
+pub fn shared_heap_size<T: ?Sized>(pointee: &T, owners: usize, f: impl FnOnce() -> usize) -> usize {
+    let addr = std::ptr::from_ref(pointee).cast::<()>() as usize;
+    kind_heap_size(MemoryKind::Shared, || {
+        visit(
+            |state| {
+                if let Some(&id) = state.shared.get(&addr) {
+                    let current = state.current;
+                    state.nodes[current].successors.push(id);
+                    return None;
+                }
+                let id = state.add_node(Label::Shared);
+                state.shared.insert(addr, id);
+                Some(id)
+            },
+            || shared_bytes(indirect_heap_size(pointee, true, f), owners),
+        )
+    })
+}
+
+/// Return the heap size of a field named `name`, where `f` returns the heap
+/// size of the field.
+///
+/// While building a [`Graph`], the field is recorded as an owner.
+pub fn field_heap_size(name: &'static str, f: impl FnOnce() -> usize) -> usize {
+    visit(
+        |state| {
+            let key = (state.current, name);
+            let id = if let Some(&id) = state.fields.get(&key) {
+                id
+            } else {
+                let id = state.add_node(Label::Field(name));
+                state.fields.insert(key, id);
+                id
+            };
+            Some(id)
+        },
+        f,
+    )
+}
+
+#[test]
+fn test_retained_shared() {
+    use std::{rc::Rc, sync::Arc};
+
+    let shared = Arc::new(vec![0u8; 64]);
+    let only_a = Rc::new(vec![0u8; 32]);
+    let a = (vec![shared.clone()], only_a.clone(), only_a);
+    let b = vec![shared.clone()];
+
+    let mut graph = Graph::new();
+    graph.add_root("a", &a);
+    graph.add_root("b", &b);
+    let report = graph.report();
+    let a_own = a.0.capacity() * 8 + 32 + mem::size_of::<Vec<u8>>();
+    assert_eq!(a_own, report.roots[0].retained);
+    assert_eq!(b.capacity() * 8, report.roots[1].retained);
+
+    // Dropping `b` frees the shared vector.
+    drop(b);
+    let mut graph = Graph::new();
+    graph.add_root("a", &a);
+    let report = graph.report();
+    assert_eq!(a_own + shared.heap_size(), report.roots[0].retained);
+}
+
+#[test]
+fn test_retained_cycle() {
+    use std::rc::Rc;
+
+    struct Node {
+        next: RefCell<Option<Rc<Node>>>,
+        data: Vec<u8>,
+    }
+    impl HeapSize for Node {
+        fn heap_size(&self) -> usize {
+            field_heap_size("next", || self.next.heap_size())
+                + field_heap_size("data", || self.data.heap_size())
+        }
+    }
+
+    let a = Rc::new(Node {
+        next: RefCell::new(None),
+        data: vec![0; 8],
+    });
+    let b = Rc::new(Node {
+        next: RefCell::new(Some(a.clone())),
+        data: vec![0; 16],
+    });
+    *a.next.borrow_mut() = Some(b.clone());
+
+    let mut graph = Graph::new();
+    graph.add_root("a", &a);
+    let report = graph.report();
+    let node = mem::size_of::<Node>();
+    assert_eq!(2 * node + 8 + 16, report.roots[0].retained);
+    assert_eq!(
+        vec![
+            Entry {
+                path: "a.next".to_owned(),
+                retained: node + 16,
+            },
+            Entry {
+                path: "a.next.next".to_owned(),
+                retained: 0,
+            },
+            Entry {
+                path: "a.next.data".to_owned(),
+                retained: 16,
+            },
+            Entry {
+                path: "a.data".to_owned(),
+                retained: 8,
+            },
+        ],
+        report.fields
+    );
+    *a.next.borrow_mut() = None;
+}
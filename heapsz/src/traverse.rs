@@ -0,0 +1,656 @@
+//! Traverse a data structure exactly, under a budget.
+//!
+//! By default, [`HeapSize::heap_size`] approximates a collection by its first
+//! element in O(1) time. A [`Traversal`] instead visits every element of every
+//! collection, until its budget runs out. After that, it stops visiting
+//! elements and extrapolates from what it has already seen.
+//!
+//! ```
+//! use std::time::{Duration, Instant};
+//!
+//! use heapsz::traverse::Traversal;
+//!
+//! let v = vec![vec![0u8; 16]; 1000];
+//! let partial = Traversal::new()
+//!     .max_elements(100)
+//!     .deadline(Instant::now() + Duration::from_millis(10))
+//!     .run(&v);
+//! assert!(partial.exhausted);
+//! assert!(partial.visited_fraction < 1.0);
+//! ```
+//!
+//! A traversal, or any closure passed to [`with_shared`], may also choose how
+//! shared allocations are charged, see [`Shared`].
+//!
+//! Within a [`Traversal`], or any closure passed to [`stops`],
+//! [`with_max_depth`] or another mode, pointers, e.g., `Box`, `Rc` and `Arc`,
+//! are guarded against cycles. A [`Traversal`], or any closure passed to
+//! [`with_max_depth`], also stops paths deeper than a maximum depth. A stopped
+//! path is reported by [`Stops`], rather than silently counted as 0. A plain
+//! [`HeapSize::heap_size`] does not pay for the guard, so it never returns on
+//! a cycle.
+//!
+//! ```
+//! use std::{cell::RefCell, rc::Rc};
+//!
+//! use heapsz::{traverse::stops, HeapSize};
+//!
+//! struct Node(RefCell<Vec<Rc<Node>>>);
+//! impl HeapSize for Node {
+//!     fn heap_size(&self) -> usize {
+//!         self.0.heap_size()
+//!     }
+//! }
+//!
+//! let node = Rc::new(Node(RefCell::new(vec![])));
+//! node.0.borrow_mut().push(node.clone());
+//! let (_, stops) = stops(|| node.heap_size());
+//! assert_eq!(1, stops.cycles);
+//! # node.0.borrow_mut().clear();
+//! ```
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    time::Instant,
+};
+
+use crate::{
+    drift::scaled,
+    estimate::{is_estimating, Samples, SAMPLES},
+    kind,
+    modes::{any_installed, Installed},
+    HeapSize,
+};
+
+/// Checking the deadline on every element is too expensive, so it is checked
+/// once per `DEADLINE_CHECK_INTERVAL` elements.
+const DEADLINE_CHECK_INTERVAL: usize = 64;
+
+/// The default maximum number of nested pointers on a path of a
+/// [`Traversal`], deeper pointees are not visited.
+///
+/// A plain `heap_size()` has no maximum depth.
+pub const DEFAULT_MAX_DEPTH: usize = 4096;
+
+/// Paths stopped by the cycle and depth guards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stops {
+    /// The number of pointers which point back to an allocation on the path,
+    /// they are counted as 0 because the allocation is already counted.
+    pub cycles: usize,
+    /// The number of pointers deeper than the maximum depth, only their
+    /// pointees themselves are counted.
+    pub depth_exceeded: usize,
+}
+
+impl Stops {
+    /// Whether no path is stopped.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        *self == Stops::default()
+    }
+}
+
+/// The result of a budgeted traversal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Partial {
+    /// The number of bytes it owns on the heap, partially extrapolated if the
+    /// budget is exhausted.
+    pub heap_size: usize,
+    /// Whether the budget ran out before all elements were visited.
+    pub exhausted: bool,
+    /// The fraction of elements that were actually visited, in `[0, 1]`.
+    pub visited_fraction: f64,
+    /// Paths stopped by the cycle and depth guards.
+    pub stops: Stops,
+}
+
+/// How a shared allocation, e.g., the `T` in an `Arc<T>`, is charged to its
+/// owners.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Shared {
+    /// Every owner is charged the whole allocation.
+    #[default]
+    Full,
+    /// Every owner is charged `memory_size(T) / strong_count`, so totals of
+    /// owners add up to the real total.
+    ///
+    /// It applies to `Rc` and `Arc`, which expose their strong counts.
+    /// `bytes::Bytes` does not, so it is still charged fully.
+    Proportional,
+}
+
+/// A budgeted exact traversal.
+#[derive(Debug, Clone, Default)]
+pub struct Traversal {
+    max_elements: Option<usize>,
+    deadline: Option<Instant>,
+    shared: Option<Shared>,
+    max_depth: Option<usize>,
+}
+
+impl Traversal {
+    /// Create a traversal without any budget, it visits every element.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop visiting elements after `max_elements` elements are visited.
+    #[must_use]
+    pub fn max_elements(mut self, max_elements: usize) -> Self {
+        self.max_elements = Some(max_elements);
+        self
+    }
+
+    /// Stop visiting elements after `deadline`.
+    #[must_use]
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Stop visiting pointees nested deeper than `max_depth` pointers,
+    /// [`DEFAULT_MAX_DEPTH`] by default.
+    #[must_use]
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Charge shared allocations by `shared` during the traversal.
+    #[must_use]
+    pub fn shared(mut self, shared: Shared) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    /// Traverse `value` and return its heap size.
+    pub fn run<T: HeapSize + ?Sized>(&self, value: &T) -> Partial {
+        self.run_with(|| value.heap_size())
+    }
+
+    /// Traverse whatever `f` visits and return the heap size returned by `f`.
+    pub fn run_with(&self, f: impl FnOnce() -> usize) -> Partial {
+        let run = || {
+            let (mut partial, stops) = stops(|| self.run_exact(f));
+            partial.stops = stops;
+            partial
+        };
+        let run = || with_max_depth(self.max_depth.unwrap_or(DEFAULT_MAX_DEPTH), run);
+        match self.shared {
+            Some(shared) => with_shared(shared, run),
+            None => run(),
+        }
+    }
+
+    fn run_exact(&self, f: impl FnOnce() -> usize) -> Partial {
+        // Restore the previous state even if `heap_size` panics.
+        struct Restore(Option<State>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                STATE.with(|s| s.set(self.0));
+            }
+        }
+        let state = State {
+            max_elements: self.max_elements,
+            deadline: self.deadline,
+            visited: 0,
+            skipped: 0,
+            exhausted: false,
+        };
+        let prev = STATE.with(|s| s.replace(Some(state)));
+        let restore = Restore(prev);
+        let installed = Installed::new();
+
+        let heap_size = f();
+        drop(installed);
+        let state = STATE.with(Cell::get).unwrap();
+        drop(restore);
+
+        let total = state.visited + state.skipped;
+        // A fraction does not need every bit of the counts.
+        #[allow(clippy::cast_precision_loss)]
+        let visited_fraction = if total == 0 {
+            1.0
+        } else {
+            state.visited as f64 / total as f64
+        };
+        Partial {
+            heap_size,
+            exhausted: state.exhausted,
+            visited_fraction,
+            stops: Stops::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    max_elements: Option<usize>,
+    deadline: Option<Instant>,
+    // The number of visited elements.
+    visited: usize,
+    // The number of extrapolated elements.
+    skipped: usize,
+    exhausted: bool,
+}
+
+impl State {
+    fn charge(&mut self) -> bool {
+        if self.exhausted {
+            return false;
+        }
+        if let Some(max_elements) = self.max_elements {
+            if self.visited >= max_elements {
+                self.exhausted = true;
+                return false;
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if self.visited % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+                self.exhausted = true;
+                return false;
+            }
+        }
+        self.visited += 1;
+        true
+    }
+}
+
+#[derive(Debug)]
+struct Guard {
+    max_depth: usize,
+    depth: usize,
+    // Addresses of shared pointees on the current path.
+    path: HashSet<usize>,
+    stops: Stops,
+}
+
+thread_local! {
+    static GUARD: RefCell<Guard> = RefCell::new(Guard {
+        max_depth: usize::MAX,
+        depth: 0,
+        path: HashSet::new(),
+        stops: Stops::default(),
+    });
+    static STATE: Cell<Option<State>> = const { Cell::new(None) };
+    static SHARED: Cell<Shared> = const { Cell::new(Shared::Full) };
+}
+
+/// Charge shared allocations by `shared` on the current thread while running
+/// `f`.
+pub fn with_shared<R>(shared: Shared, f: impl FnOnce() -> R) -> R {
+    // Restore the previous mode even if `f` panics.
+    struct Restore(Shared);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SHARED.with(|s| s.set(self.0));
+        }
+    }
+    let prev = SHARED.with(|s| s.replace(shared));
+    let _restore = Restore(prev);
+    let _installed = Installed::new();
+    f()
+}
+
+/// Run `f` with a maximum depth of nested pointers on the current thread.
+pub fn with_max_depth<R>(max_depth: usize, f: impl FnOnce() -> R) -> R {
+    // Restore the previous limit even if `f` panics.
+    struct Restore(usize);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            GUARD.with(|g| g.borrow_mut().max_depth = self.0);
+        }
+    }
+    let prev = GUARD.with(|g| std::mem::replace(&mut g.borrow_mut().max_depth, max_depth));
+    let _restore = Restore(prev);
+    let _installed = Installed::new();
+    f()
+}
+
+/// Run `f` and return paths stopped by the cycle and depth guards in `f`.
+pub fn stops<R>(f: impl FnOnce() -> R) -> (R, Stops) {
+    // Add up to the outer stops even if `f` panics.
+    struct Merge(Stops);
+    impl Drop for Merge {
+        fn drop(&mut self) {
+            GUARD.with(|g| {
+                let stops = &mut g.borrow_mut().stops;
+                stops.cycles += self.0.cycles;
+                stops.depth_exceeded += self.0.depth_exceeded;
+            });
+        }
+    }
+    let outer = GUARD.with(|g| std::mem::take(&mut g.borrow_mut().stops));
+    let _merge = Merge(outer);
+    let _installed = Installed::new();
+    let res = f();
+    (res, GUARD.with(|g| g.borrow().stops))
+}
+
+/// Return the heap size of `pointee` behind a pointer, where `f` returns the
+/// number of bytes of the allocation.
+///
+/// While a mode is installed, e.g., by [`Traversal`] or [`stops`], if the
+/// pointer is `shared`, e.g., `Rc` and `Arc`, and its pointee is being
+/// visited on the current path, it is a cycle and 0 is returned. If the path
+/// is deeper than the maximum depth, only `size_of_val(pointee)` is returned.
+/// Both are recorded in [`Stops`]. Otherwise it is just `f()`.
+#[inline]
+pub fn indirect_heap_size<T: ?Sized>(
+    pointee: &T,
+    shared: bool,
+    f: impl FnOnce() -> usize,
+) -> usize {
+    if !any_installed() {
+        return f();
+    }
+    guarded_heap_size(pointee, shared, f)
+}
+
+#[inline(never)]
+fn guarded_heap_size<T: ?Sized>(pointee: &T, shared: bool, f: impl FnOnce() -> usize) -> usize {
+    // Leave the pointee even if `f` panics.
+    struct Leave(usize, bool);
+    impl Drop for Leave {
+        fn drop(&mut self) {
+            GUARD.with(|g| {
+                let mut g = g.borrow_mut();
+                g.depth -= 1;
+                if self.1 {
+                    g.path.remove(&self.0);
+                }
+            });
+        }
+    }
+    let addr = std::ptr::from_ref(pointee).cast::<()>() as usize;
+    let enter = GUARD.with(|g| {
+        let mut g = g.borrow_mut();
+        if g.depth >= g.max_depth {
+            g.stops.depth_exceeded += 1;
+            return Err(std::mem::size_of_val(pointee));
+        }
+        if shared && !g.path.insert(addr) {
+            g.stops.cycles += 1;
+            return Err(0);
+        }
+        g.depth += 1;
+        Ok(())
+    });
+    if let Err(bytes) = enter {
+        return bytes;
+    }
+    let _leave = Leave(addr, shared);
+    f()
+}
+
+/// Return the number of bytes charged to one of `owners` owners of a shared
+/// allocation of `bytes` bytes, in the current [`Shared`] mode.
+pub fn shared_bytes(bytes: usize, owners: usize) -> usize {
+    if !any_installed() {
+        return bytes;
+    }
+    match SHARED.with(Cell::get) {
+        Shared::Full => bytes,
+        Shared::Proportional => bytes / owners.max(1),
+    }
+}
+
+/// Whether a traversal is running on the current thread.
+pub(crate) fn is_traversing() -> bool {
+    STATE.with(Cell::get).is_some()
+}
+
+fn update<R>(f: impl FnOnce(&mut State) -> R) -> Option<R> {
+    STATE.with(|s| {
+        let mut state = s.get()?;
+        let res = f(&mut state);
+        s.set(Some(state));
+        Some(res)
+    })
+}
+
+/// Sum up heap sizes of `len` elements of type `T` in a collection, where
+/// `heap_sizes` lazily yields heap sizes of elements.
+///
+/// Out of a traversal, it approximates by the first element, because we want
+/// the time complexity to be O(1). In a traversal, it visits elements until
+/// the budget runs out, then extrapolates the rest from visited elements.
+/// In an [`estimate`](crate::estimate::estimate), it samples a few elements and
+/// bounds the rest by them.
+///
+/// Elements that do not need drop can not own any heap memory, they are
+/// never visited, e.g., bytes in a `Vec<u8>`.
+#[inline]
+pub fn elements_heap_size<T>(len: usize, mut heap_sizes: impl Iterator<Item = usize>) -> usize {
+    if len == 0 {
+        return 0;
+    }
+    if !any_installed() {
+        return heap_sizes.next().map_or(0, |h| len * h);
+    }
+    moded_elements_heap_size::<T>(len, heap_sizes)
+}
+
+#[inline(never)]
+fn moded_elements_heap_size<T>(len: usize, mut heap_sizes: impl Iterator<Item = usize>) -> usize {
+    let traversing = is_traversing();
+    if !std::mem::needs_drop::<T>() || !(traversing || is_estimating()) {
+        return scaled(len, || kind::scaled(len, || heap_sizes.next())).map_or(0, |h| len * h);
+    }
+
+    let mut samples = Samples::new(len);
+    while samples.count() < len {
+        let charged = if traversing {
+            update(State::charge) == Some(true)
+        } else {
+            samples.count() < SAMPLES
+        };
+        if !charged {
+            break;
+        }
+        // Do not hold any state while visiting an element, it may be
+        // another collection.
+        match heap_sizes.next() {
+            Some(h) => samples.push(h),
+            None => break,
+        }
+    }
+    let visited = samples.count();
+    if traversing && visited < len {
+        update(|s| s.skipped += len - visited);
+    }
+    if visited == 0 {
+        // Approximate by the first element.
+        if let Some(h) = heap_sizes.next() {
+            samples.push(h);
+        }
+    }
+    samples.finish()
+}
+
+#[test]
+fn test_traversal_exact() {
+    let mut v: Vec<Vec<u8>> = Vec::with_capacity(10);
+    for i in 0..10 {
+        v.push(Vec::with_capacity(i));
+    }
+    let exact = v.capacity() * std::mem::size_of::<Vec<u8>>() + (0..10).sum::<usize>();
+    // `heap_size()` approximates by the first element, which is empty.
+    assert_eq!(v.capacity() * std::mem::size_of::<Vec<u8>>(), v.heap_size());
+
+    let partial = Traversal::new().run(&v);
+    assert_eq!(
+        Partial {
+            heap_size: exact,
+            exhausted: false,
+            visited_fraction: 1.0,
+            stops: Stops::default(),
+        },
+        partial
+    );
+
+    // Traversal state does not leak.
+    assert_eq!(v.capacity() * std::mem::size_of::<Vec<u8>>(), v.heap_size());
+}
+
+#[test]
+fn test_traversal_max_elements() {
+    let v: Vec<Vec<u8>> = (0..10).map(|_| vec![0; 8]).collect();
+    let cap_bytes = v.capacity() * std::mem::size_of::<Vec<u8>>();
+
+    let partial = Traversal::new().max_elements(5).run(&v);
+    assert!(partial.exhausted);
+    assert_eq!(0.5, partial.visited_fraction);
+    // Extrapolated from visited elements.
+    assert_eq!(cap_bytes + 10 * 8, partial.heap_size);
+
+    let partial = Traversal::new().max_elements(0).run(&v);
+    assert!(partial.exhausted);
+    assert_eq!(0.0, partial.visited_fraction);
+    assert_eq!(cap_bytes + 10 * 8, partial.heap_size);
+
+    let partial = Traversal::new().max_elements(10).run(&v);
+    assert!(!partial.exhausted);
+    assert_eq!(cap_bytes + 10 * 8, partial.heap_size);
+}
+
+#[test]
+fn test_traversal_nested() {
+    use std::collections::HashMap;
+
+    let mut map: HashMap<u64, Vec<Vec<u8>>> = HashMap::new();
+    for i in 0..4 {
+        map.insert(i, vec![vec![0; 4]; 4]);
+    }
+    let partial = Traversal::new().run(&map);
+    assert_eq!(map.heap_size(), partial.heap_size);
+    assert_eq!(1.0, partial.visited_fraction);
+
+    let partial = Traversal::new().max_elements(3).run(&map);
+    assert!(partial.exhausted);
+    assert_eq!(map.heap_size(), partial.heap_size);
+    assert!(partial.visited_fraction < 1.0);
+}
+
+#[test]
+fn test_traversal_shared() {
+    use std::sync::Arc;
+
+    let a = Arc::new(vec![0u8; 60]);
+    let v = vec![a.clone(), a.clone(), a];
+    let cap_bytes = v.capacity() * std::mem::size_of::<Arc<Vec<u8>>>();
+    let full = Traversal::new().run(&v).heap_size;
+    assert_eq!(cap_bytes + 3 * v[0].heap_size(), full);
+
+    let proportional = Traversal::new().shared(Shared::Proportional).run(&v);
+    assert_eq!(cap_bytes + v[0].heap_size(), proportional.heap_size);
+    assert_eq!(
+        v[0].heap_size() / 3,
+        with_shared(Shared::Proportional, || v[0].heap_size())
+    );
+    assert_eq!(v[0].heap_size(), shared_bytes(v[0].heap_size(), 3));
+}
+
+#[test]
+fn test_traversal_cycle() {
+    use std::rc::Rc;
+
+    struct Node(RefCell<Vec<Rc<Node>>>);
+    impl HeapSize for Node {
+        fn heap_size(&self) -> usize {
+            self.0.heap_size()
+        }
+    }
+
+    let a = Rc::new(Node(RefCell::new(vec![])));
+    let b = Rc::new(Node(RefCell::new(vec![a.clone()])));
+    a.0.borrow_mut().push(b.clone());
+    let vec_bytes = |n: &Node| n.0.borrow().capacity() * std::mem::size_of::<Rc<Node>>();
+
+    let partial = Traversal::new().run(&a);
+    assert_eq!(
+        2 * std::mem::size_of::<Node>() + vec_bytes(&a) + vec_bytes(&b),
+        partial.heap_size
+    );
+    assert_eq!(
+        Stops {
+            cycles: 1,
+            depth_exceeded: 0,
+        },
+        partial.stops
+    );
+    // The approximate mode is guarded too.
+    let (heap_size, s) = stops(|| a.heap_size());
+    assert_eq!(partial.heap_size, heap_size);
+    assert_eq!(partial.stops, s);
+
+    // A shared pointee which is not on the path is not a cycle.
+    let v = vec![b.clone(), b.clone()];
+    assert_eq!(2, Traversal::new().run(&v).stops.cycles);
+
+    a.0.borrow_mut().clear();
+}
+
+#[test]
+fn test_traversal_max_depth() {
+    struct List(Option<Box<List>>);
+    impl HeapSize for List {
+        fn heap_size(&self) -> usize {
+            self.0.heap_size()
+        }
+    }
+
+    let mut list = List(None);
+    for _ in 0..10 {
+        list = List(Some(Box::new(list)));
+    }
+    let node = std::mem::size_of::<List>();
+    assert_eq!(10 * node, list.heap_size());
+
+    let partial = Traversal::new().max_depth(3).run(&list);
+    assert_eq!(4 * node, partial.heap_size);
+    assert_eq!(1, partial.stops.depth_exceeded);
+    assert!(!partial.stops.is_empty());
+
+    let (heap_size, s) = stops(|| with_max_depth(5, || list.heap_size()));
+    assert_eq!(6 * node, heap_size);
+    assert_eq!(1, s.depth_exceeded);
+    assert!(stops(|| list.heap_size()).1.is_empty());
+
+    // Only a traversal has a default maximum depth.
+    let mut list = List(None);
+    for _ in 0..5000 {
+        list = List(Some(Box::new(list)));
+    }
+    assert_eq!(5000 * node, list.heap_size());
+    // The guard takes more stack per pointer than a plain `heap_size()`.
+    std::thread::scope(|s| {
+        std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn_scoped(s, || {
+                assert_eq!(5000 * node, stops(|| list.heap_size()).0);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    });
+    let partial = Traversal::new().run(&list);
+    assert_eq!((DEFAULT_MAX_DEPTH + 1) * node, partial.heap_size);
+    assert_eq!(1, partial.stops.depth_exceeded);
+    // Avoid overflowing the stack by the recursive drop.
+    while let Some(next) = list.0.take() {
+        list = *next;
+    }
+}
+
+#[test]
+fn test_traversal_deadline() {
+    let v: Vec<Vec<u8>> = (0..1000).map(|_| vec![0; 8]).collect();
+    let partial = Traversal::new().deadline(Instant::now()).run(&v);
+    assert!(partial.exhausted);
+    assert_eq!(0.0, partial.visited_fraction);
+    assert_eq!(v.heap_size(), partial.heap_size);
+}
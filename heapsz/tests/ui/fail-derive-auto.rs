@@ -0,0 +1,30 @@
+use heapsz_derive::HeapSize;
+
+pub struct Opaque;
+
+#[derive(HeapSize)]
+#[heap_size(auto)]
+pub struct S1 {
+    pub a: Vec<u8>,
+    pub b: Opaque,
+}
+
+#[derive(HeapSize)]
+#[heap_size(auto)]
+pub struct S2<T> {
+    pub a: Option<T>,
+}
+
+#[derive(HeapSize)]
+#[heap_size(auto)]
+pub enum E1 {
+    A(u64, (u8, Opaque)),
+}
+
+#[derive(HeapSize)]
+pub struct S3 {
+    #[heap_size(auto)]
+    pub a: Vec<u8>,
+}
+
+fn main() {}
@@ -0,0 +1,28 @@
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+pub struct B1<T> {
+    #[heap_size(bound)]
+    pub a: Vec<T>,
+}
+
+#[derive(HeapSize)]
+pub struct B2<T> {
+    #[heap_size(bound = "T HeapSize")]
+    pub a: Vec<T>,
+}
+
+#[derive(HeapSize)]
+#[heap_size(bound = "", bound = "T: Clone")]
+pub struct B3<T> {
+    pub a: Vec<T>,
+}
+
+#[derive(HeapSize)]
+#[heap_size]
+pub enum B4<T> {
+    #[heap_size(bound = "T: Clone")]
+    A(Vec<T>),
+}
+
+fn main() {}
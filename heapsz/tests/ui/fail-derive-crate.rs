@@ -0,0 +1,21 @@
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+#[heap_size(crate)]
+pub struct C1 {
+    pub a: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+pub struct C2 {
+    #[heap_size(crate = "heapsz")]
+    pub a: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+#[heap_size(crate = "not a path")]
+pub struct C3 {
+    pub a: Vec<u8>,
+}
+
+fn main() {}
@@ -0,0 +1,41 @@
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+#[heap_size(exhaustive)]
+pub struct S1 {
+    #[heap_size]
+    pub a: Vec<u8>,
+    pub b: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+#[heap_size(exhaustive)]
+pub struct T1(#[heap_size] Vec<u8>, #[heap_size(bound = "")] Vec<u8>);
+
+#[derive(HeapSize)]
+#[heap_size(exhaustive)]
+pub enum E1 {
+    A(#[heap_size] Vec<u8>),
+    B { len: usize },
+}
+
+#[derive(HeapSize)]
+#[heap_size]
+#[heap_size(exhaustive)]
+pub struct S2 {
+    pub a: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+pub struct S3 {
+    #[heap_size(exhaustive)]
+    pub a: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+#[heap_size(zero)]
+pub struct S4 {
+    pub a: Vec<u8>,
+}
+
+fn main() {}
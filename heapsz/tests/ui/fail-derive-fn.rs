@@ -0,0 +1,53 @@
+use heapsz_derive::HeapSize;
+
+pub fn wrong_arg(v: &String) -> usize {
+    v.len()
+}
+
+pub fn wrong_ret(v: &Vec<u8>) -> u32 {
+    v.len() as u32
+}
+
+#[derive(HeapSize)]
+pub struct S1 {
+    #[heap_size(fn = "missing::heap_size")]
+    pub a: Vec<u8>,
+    #[heap_size(fn = "wrong_arg")]
+    pub b: Vec<u8>,
+    #[heap_size(fn = "wrong_ret")]
+    pub c: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+pub struct S2 {
+    #[heap_size(expr = "self.missing.len()")]
+    pub a: Vec<u8>,
+    #[heap_size(expr = "self.b.len() as u32")]
+    pub b: Vec<u8>,
+    #[heap_size(add = -1)]
+    pub c: Vec<u8>,
+    #[heap_size(add = "self.d.len()")]
+    pub d: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+pub struct S3 {
+    #[heap_size(expr = "self.a.len() +")]
+    pub a: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+pub struct S4 {
+    #[heap_size(add)]
+    pub a: Vec<u8>,
+    #[heap_size(expr)]
+    pub b: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+#[heap_size(add = 1)]
+pub struct S5 {
+    pub a: Vec<u8>,
+}
+
+fn main() {}
@@ -1,9 +1,9 @@
 use heapsz_derive::HeapSize;
 
 #[derive(HeapSize)]
-pub struct G1<T> {
-    #[heap_size]
-    pub a: T,
+#[heap_size(par)]
+pub struct S1 {
+    pub a: Vec<Vec<u8>>,
 }
 
 fn main() {}
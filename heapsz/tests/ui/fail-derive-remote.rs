@@ -0,0 +1,69 @@
+use heapsz_derive::HeapSize;
+
+mod other_crate {
+    pub struct Meta {
+        pub name: String,
+        pub id: u64,
+    }
+
+    pub enum Value {
+        Str(String),
+        Null,
+    }
+}
+
+// Missing a field.
+#[derive(HeapSize)]
+#[heap_size(remote = "other_crate::Meta")]
+pub struct MetaDef1 {
+    #[heap_size]
+    pub name: String,
+}
+
+// An unknown field.
+#[derive(HeapSize)]
+#[heap_size(remote = "other_crate::Meta")]
+pub struct MetaDef2 {
+    #[heap_size]
+    pub name: String,
+    pub id: u64,
+    pub len: usize,
+}
+
+// A mismatched type.
+#[derive(HeapSize)]
+#[heap_size(remote = "other_crate::Meta")]
+pub struct MetaDef3 {
+    #[heap_size]
+    pub name: Vec<u8>,
+    pub id: u64,
+}
+
+// Missing a variant.
+#[derive(HeapSize)]
+#[heap_size(remote = "other_crate::Value")]
+pub enum ValueDef {
+    Str(#[heap_size] String),
+}
+
+#[derive(HeapSize)]
+#[heap_size(remote)]
+pub struct S1 {
+    pub a: u64,
+}
+
+#[derive(HeapSize)]
+pub struct S2 {
+    #[heap_size(remote = "other_crate::Meta")]
+    pub a: u64,
+}
+
+#[derive(HeapSize)]
+#[heap_size(remote = "other_crate::Meta")]
+pub struct MetaDef4 {
+    #[heap_size(par)]
+    pub name: String,
+    pub id: u64,
+}
+
+fn main() {}
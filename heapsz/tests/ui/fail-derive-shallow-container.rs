@@ -0,0 +1,9 @@
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+#[heap_size(shallow)]
+pub struct S1 {
+    pub a: Vec<Vec<u8>>,
+}
+
+fn main() {}
@@ -6,4 +6,49 @@ union Union {
     f2: f32,
 }
 
+#[derive(HeapSize)]
+#[heap_size(active)]
+union Union2 {
+    f1: u32,
+}
+
+#[derive(HeapSize)]
+#[heap_size(active = "active")]
+pub struct S1 {
+    pub a: u32,
+}
+
+#[derive(HeapSize)]
+#[heap_size(fn)]
+pub struct S3 {
+    pub a: u32,
+}
+
+#[derive(HeapSize)]
+#[heap_size(with = "missing")]
+pub struct S4 {
+    pub a: u32,
+}
+
+#[derive(HeapSize)]
+#[heap_size(active = "active")]
+union Union3 {
+    #[heap_size(skip)]
+    f1: u32,
+}
+
+// The active method must be an `unsafe fn`.
+#[derive(HeapSize)]
+#[heap_size(active = "active")]
+union Union4 {
+    #[heap_size]
+    f1: u32,
+}
+
+impl Union4 {
+    fn active(&self) -> &str {
+        "f1"
+    }
+}
+
 fn main() {}
@@ -0,0 +1,25 @@
+use heapsz_derive::HeapSize;
+
+pub fn one_arg(a: &Vec<u8>) -> usize {
+    a.len()
+}
+
+#[derive(HeapSize)]
+pub enum E1 {
+    #[heap_size(with_ctx = "ctx")]
+    A(Vec<u8>),
+}
+
+#[derive(HeapSize)]
+pub enum E2 {
+    #[heap_size(fn = "one_arg")]
+    A(Vec<u8>, usize),
+}
+
+#[derive(HeapSize)]
+pub enum E3 {
+    #[heap_size(par)]
+    A(Vec<u8>),
+}
+
+fn main() {}
@@ -0,0 +1,21 @@
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+pub struct S1 {
+    #[heap_size(kind)]
+    pub a: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+pub struct S2 {
+    #[heap_size(kind = "gpu")]
+    pub a: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+#[heap_size(kind = "mmap")]
+pub struct S3 {
+    pub a: Vec<u8>,
+}
+
+fn main() {}
@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+pub struct S1 {
+    #[heap_size(shared)]
+    pub a: Arc<Vec<u8>>,
+}
+
+#[derive(HeapSize)]
+pub struct S2 {
+    #[heap_size(shared = "half")]
+    pub a: Arc<Vec<u8>>,
+}
+
+#[derive(HeapSize)]
+#[heap_size(shared = "proportional")]
+pub struct S3 {
+    pub a: Arc<Vec<u8>>,
+}
+
+fn main() {}
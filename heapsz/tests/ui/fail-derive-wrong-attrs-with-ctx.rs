@@ -0,0 +1,15 @@
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+pub struct S1 {
+    #[heap_size(with_ctx)]
+    pub a: usize,
+}
+
+#[derive(HeapSize)]
+pub struct S2 {
+    #[heap_size(with_ctx = "Not A Type")]
+    pub a: usize,
+}
+
+fn main() {}
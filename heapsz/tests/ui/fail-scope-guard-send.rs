@@ -0,0 +1,5 @@
+// A scope is restored on the thread that entered it.
+fn main() {
+    let guard = heapsz::scope("send");
+    std::thread::spawn(move || drop(guard)).join().unwrap();
+}
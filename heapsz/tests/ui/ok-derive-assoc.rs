@@ -0,0 +1,33 @@
+use heapsz::HeapSize as _;
+use heapsz_derive::HeapSize;
+
+pub trait Store {
+    type Item;
+    type Key;
+}
+
+// A store that does not implement `HeapSize` itself.
+pub struct VecStore;
+
+impl Store for VecStore {
+    type Item = Vec<u8>;
+    type Key = String;
+}
+
+// `S::Item: HeapSize` and `<S as Store>::Key: HeapSize` are inferred, rather
+// than `S: HeapSize`.
+#[derive(HeapSize)]
+pub struct Entry<S: Store> {
+    #[heap_size]
+    pub items: Vec<S::Item>,
+    #[heap_size]
+    pub key: <S as Store>::Key,
+}
+
+fn main() {
+    let entry: Entry<VecStore> = Entry {
+        items: vec![vec![0u8; 4]],
+        key: String::from("key"),
+    };
+    assert_eq!(entry.items.heap_size() + entry.key.heap_size(), entry.heap_size());
+}
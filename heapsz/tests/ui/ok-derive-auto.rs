@@ -0,0 +1,95 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    sync::{atomic::AtomicU64, Arc},
+    time::Duration,
+};
+
+use heapsz::HeapSize as _;
+use heapsz_derive::HeapSize;
+
+pub struct Opaque;
+
+#[derive(HeapSize)]
+#[heap_size(auto)]
+pub struct S1<T> {
+    pub id: u64,
+    pub ttl: Option<Duration>,
+    pub hits: AtomicU64,
+    pub name: String,
+    pub tags: Option<Vec<String>>,
+    pub index: HashMap<u32, Box<[u8]>>,
+    pub ranges: BTreeMap<u64, (u64, u64)>,
+    pub shared: Arc<Vec<u8>>,
+    pub pair: (u32, Vec<u8>),
+    pub items: Vec<T>,
+    pub _marker: PhantomData<T>,
+    #[heap_size(skip)]
+    pub opaque: Opaque,
+    #[heap_size(zero)]
+    pub unit: Opaque,
+}
+
+#[derive(HeapSize)]
+#[heap_size(auto)]
+pub enum E1 {
+    A(Vec<u8>, u32),
+    B { name: Option<String> },
+    #[heap_size(skip)]
+    C(Opaque),
+    D,
+}
+
+#[cfg(feature = "bytes")]
+mod bytes {
+    use heapsz::HeapSize as _;
+    use heapsz_derive::HeapSize;
+
+    #[derive(HeapSize)]
+    #[heap_size(auto)]
+    pub struct S2 {
+        pub data: ::bytes::Bytes,
+        pub buf: Option<::bytes::BytesMut>,
+    }
+
+    pub fn check() {
+        let s2 = S2 {
+            data: ::bytes::Bytes::from(vec![0; 8]),
+            buf: None,
+        };
+        assert_eq!(8, s2.heap_size());
+    }
+}
+
+fn main() {
+    let s1 = S1::<u8> {
+        id: 1,
+        ttl: None,
+        hits: AtomicU64::new(0),
+        name: "name".to_owned(),
+        tags: None,
+        index: HashMap::new(),
+        ranges: BTreeMap::new(),
+        shared: Arc::new(vec![]),
+        pair: (1, vec![0; 4]),
+        items: vec![0; 8],
+        _marker: PhantomData,
+        opaque: Opaque,
+        unit: Opaque,
+    };
+    assert_eq!(
+        s1.name.heap_size()
+            + s1.shared.heap_size()
+            + s1.pair.heap_size()
+            + s1.items.heap_size(),
+        s1.heap_size()
+    );
+
+    assert_eq!(4, E1::A(vec![0; 4], 1).heap_size());
+    assert_eq!(4, E1::B { name: Some("name".to_owned()) }.heap_size());
+    assert_eq!(0, E1::C(Opaque).heap_size());
+    assert_eq!(0, E1::D.heap_size());
+
+    #[cfg(feature = "bytes")]
+    bytes::check();
+}
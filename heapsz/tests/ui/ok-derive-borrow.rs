@@ -0,0 +1,46 @@
+use heapsz::{HeapSize as _, ShallowHeapSize as _};
+use heapsz_derive::HeapSize;
+
+pub struct NoneHeapSize;
+
+#[derive(HeapSize)]
+#[heap_size]
+pub struct Borrowed<'a, T> {
+    pub bytes: &'a [u8],
+    pub name: &'a str,
+    pub owner: &'a Vec<u8>,
+    pub none: &'a NoneHeapSize,
+    pub generic: &'a mut T,
+    pub names: Vec<&'a str>,
+}
+
+#[derive(HeapSize)]
+pub struct Shallow<'a> {
+    #[heap_size(shallow)]
+    pub owner: &'a Vec<String>,
+    #[heap_size(shallow)]
+    pub names: Vec<&'a str>,
+}
+
+fn main() {
+    let owner = vec![0u8; 64];
+    let mut generic = NoneHeapSize;
+    let names = vec!["a", "b"];
+    let b = Borrowed {
+        bytes: &owner,
+        name: "name",
+        owner: &owner,
+        none: &NoneHeapSize,
+        generic: &mut generic,
+        names: names.clone(),
+    };
+    // Only the `Vec` of borrows is owned.
+    assert_eq!(names.shallow_heap_size(), b.heap_size());
+
+    let strings = vec!["x".repeat(8)];
+    let s = Shallow {
+        owner: &strings,
+        names,
+    };
+    assert_eq!(s.names.shallow_heap_size(), s.heap_size());
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+// A utility crate that re-exports heapsz.
+mod utils {
+    pub use heapsz as heap;
+}
+
+use utils::heap::HeapSize;
+
+#[derive(heapsz_derive::HeapSize)]
+#[heap_size(crate = "utils::heap")]
+pub struct S1<T> {
+    #[heap_size]
+    pub a: Vec<T>,
+    #[heap_size(shared = "proportional")]
+    pub b: Arc<Vec<u8>>,
+    #[heap_size(kind = "mmap")]
+    pub c: Vec<u8>,
+}
+
+#[derive(heapsz_derive::HeapSize)]
+#[heap_size(crate = "crate::utils::heap")]
+#[heap_size]
+pub enum E1 {
+    A(Vec<u8>),
+    B {
+        #[heap_size(skip)]
+        b: Vec<u8>,
+    },
+}
+
+fn main() {
+    let s = S1 {
+        a: vec![0u16; 2],
+        b: Arc::new(vec![0u8; 4]),
+        c: vec![0u8; 8],
+    };
+    assert_eq!(4 + s.b.heap_size() + 8, s.heap_size());
+    assert_eq!(3, E1::A(vec![0; 3]).heap_size());
+}
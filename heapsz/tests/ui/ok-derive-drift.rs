@@ -0,0 +1,52 @@
+use std::sync::{Arc, Mutex};
+
+use heapsz::{
+    drift::{self, Detector},
+    HeapSize as _,
+};
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+pub struct Row {
+    #[heap_size]
+    pub cells: Vec<String>,
+}
+
+#[derive(HeapSize)]
+pub struct Table {
+    #[heap_size]
+    pub name: String,
+    #[heap_size]
+    pub rows: Vec<Row>,
+}
+
+fn main() {
+    let drifts = Arc::new(Mutex::new(vec![]));
+    let drifts1 = drifts.clone();
+    Detector::new(move |d| drifts1.lock().unwrap().push(d.clone()))
+        .every(1)
+        .threshold(0.2)
+        .install();
+
+    // The first row is empty, but the others are not.
+    let mut rows = vec![Row { cells: vec![] }];
+    rows.extend((0..9).map(|_| Row {
+        cells: vec!["x".repeat(64); 4],
+    }));
+    let table = Table {
+        name: "table".to_owned(),
+        rows,
+    };
+    let estimate = table.heap_size();
+    drift::uninstall();
+
+    let drifts = drifts.lock().unwrap();
+    assert_eq!(1, drifts.len());
+    assert!(drifts[0].type_name.ends_with("Table"));
+    assert_eq!("rows.cells", drifts[0].field_path);
+    assert_eq!(estimate, drifts[0].estimate);
+    assert_eq!(
+        heapsz::traverse::Traversal::new().run(&table).heap_size,
+        drifts[0].exact
+    );
+}
@@ -0,0 +1,65 @@
+use std::sync::Mutex;
+
+use heapsz::HeapSize as _;
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+pub struct Exact {
+    #[heap_size]
+    pub buf: Vec<u8>,
+    #[heap_size]
+    pub name: String,
+}
+
+#[derive(HeapSize)]
+pub struct Sampled {
+    #[heap_size]
+    pub rows: Vec<Vec<u8>>,
+    pub id: u64,
+}
+
+#[derive(HeapSize)]
+#[heap_size]
+pub struct Skipped {
+    pub buf: Vec<u8>,
+    #[heap_size(skip)]
+    pub opaque: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+pub enum Locked {
+    A(#[heap_size] Mutex<Vec<u8>>),
+}
+
+fn main() {
+    let exact = Exact {
+        buf: vec![0; 16],
+        name: "name".to_owned(),
+    };
+    let estimate = exact.heap_size_estimate();
+    assert!(estimate.exact);
+    assert_eq!(exact.heap_size(), estimate.value);
+    assert_eq!(estimate.lower, estimate.upper);
+
+    let sampled = Sampled {
+        rows: (0..100).map(|i| vec![0; i]).collect(),
+        id: 0,
+    };
+    let estimate = sampled.heap_size_estimate();
+    assert!(!estimate.exact);
+    assert!(estimate.lower < estimate.value && estimate.value < estimate.upper);
+    assert!(estimate.upper < usize::MAX);
+
+    let skipped = Skipped {
+        buf: vec![0; 16],
+        opaque: vec![0; 16],
+    };
+    let estimate = skipped.heap_size_estimate();
+    assert!(!estimate.exact);
+    assert_eq!((16, 16, usize::MAX), (estimate.value, estimate.lower, estimate.upper));
+
+    let locked = Locked::A(Mutex::new(vec![0; 16]));
+    let Locked::A(m) = &locked;
+    let _guard = m.lock().unwrap();
+    assert_eq!(usize::MAX, locked.heap_size_estimate().upper);
+}
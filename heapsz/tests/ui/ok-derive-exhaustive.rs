@@ -0,0 +1,65 @@
+use heapsz::HeapSize as _;
+use heapsz_derive::HeapSize;
+
+mod len {
+    pub fn heap_size(v: &[u8]) -> usize {
+        v.len()
+    }
+}
+
+#[derive(HeapSize)]
+#[heap_size(exhaustive)]
+pub struct S1 {
+    #[heap_size]
+    pub a: Vec<u8>,
+    #[heap_size(skip)]
+    pub b: Vec<u8>,
+    #[heap_size(zero)]
+    pub c: u64,
+    #[heap_size(with = "len")]
+    pub d: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+#[heap_size(exhaustive)]
+pub struct T1(#[heap_size] Vec<u8>, #[heap_size(zero)] usize);
+
+#[derive(HeapSize)]
+#[heap_size(exhaustive)]
+pub enum E1 {
+    A(#[heap_size] Vec<u8>, #[heap_size(zero)] bool),
+    #[heap_size(skip)]
+    B(Vec<u8>),
+    C,
+}
+
+#[derive(HeapSize)]
+#[heap_size]
+pub struct S2 {
+    pub a: Vec<u8>,
+    #[heap_size(zero)]
+    pub b: u32,
+}
+
+fn main() {
+    let s1 = S1 {
+        a: vec![0; 8],
+        b: vec![0; 8],
+        c: 1,
+        d: Vec::with_capacity(16),
+    };
+    assert_eq!(8, s1.heap_size());
+    let estimate = s1.heap_size_estimate();
+    assert_eq!(8, estimate.lower);
+    assert!(!estimate.exact);
+
+    assert_eq!(4, T1(vec![0; 4], 1).heap_size());
+    assert!(T1(vec![0; 4], 1).heap_size_estimate().exact);
+
+    assert_eq!(2, E1::A(vec![0; 2], true).heap_size());
+    assert_eq!(0, E1::B(vec![0; 2]).heap_size());
+    assert_eq!(0, E1::C.heap_size());
+
+    assert_eq!(4, S2 { a: vec![0; 4], b: 1 }.heap_size());
+    assert!(S2 { a: vec![0; 4], b: 1 }.heap_size_estimate().exact);
+}
@@ -0,0 +1,81 @@
+use heapsz::HeapSize as _;
+use heapsz_derive::HeapSize;
+
+pub struct Ctx(*mut u8);
+
+pub const CTX_SIZE: usize = 4096;
+
+pub fn doubled(v: &[u8]) -> usize {
+    v.len() * 2
+}
+
+mod sizes {
+    pub fn capacity<T>(v: &Vec<T>) -> usize {
+        v.capacity() * std::mem::size_of::<T>()
+    }
+}
+
+#[derive(HeapSize)]
+pub struct S1 {
+    #[heap_size(fn = "doubled")]
+    pub a: Vec<u8>,
+    #[heap_size(fn = "sizes::capacity")]
+    pub b: Vec<u32>,
+    #[heap_size(expr = "self.c.len() * 2")]
+    pub c: Vec<u8>,
+    #[heap_size(add = 4096)]
+    pub ctx: Ctx,
+    #[heap_size(add = "CTX_SIZE / 2")]
+    pub half: Ctx,
+    #[heap_size(add = 64)]
+    pub d: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+#[heap_size]
+pub struct S2 {
+    #[heap_size(add = 16)]
+    pub a: Vec<u8>,
+    #[heap_size(skip, add = 8)]
+    pub b: Vec<u8>,
+    #[heap_size(fn = "doubled", add = 1)]
+    pub c: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+#[heap_size(exhaustive)]
+pub enum E1 {
+    A(#[heap_size(add = CTX_SIZE)] Ctx),
+    B {
+        #[heap_size(fn = "doubled")]
+        v: Vec<u8>,
+    },
+}
+
+fn main() {
+    let ctx = || Ctx(std::ptr::null_mut());
+    let s1 = S1 {
+        a: vec![0; 3],
+        b: Vec::with_capacity(2),
+        c: vec![0; 5],
+        ctx: ctx(),
+        half: ctx(),
+        d: vec![0; 4],
+    };
+    // Without other attributes, `add` is all the field owns.
+    assert_eq!(6 + 8 + 10 + 4096 + 2048 + 64, s1.heap_size());
+
+    let s2 = S2 {
+        a: vec![0; 4],
+        b: vec![0; 4],
+        c: vec![0; 4],
+    };
+    assert_eq!(4 + 16 + 8 + 8 + 1, s2.heap_size());
+    let estimate = s2.heap_size_estimate();
+    assert_eq!(37, estimate.lower);
+    assert!(!estimate.exact);
+
+    assert_eq!(4096, E1::A(ctx()).heap_size());
+    assert_eq!(4, E1::B { v: vec![0; 2] }.heap_size());
+    let _ = (s1.ctx.0, s1.half.0);
+}
@@ -0,0 +1,75 @@
+use std::{collections::HashMap, marker::PhantomData};
+
+use heapsz::HeapSize as _;
+use heapsz_derive::HeapSize;
+
+// `T: HeapSize` is inferred.
+#[derive(HeapSize)]
+pub struct G1<T> {
+    #[heap_size]
+    pub a: T,
+}
+
+// Only `K` and `V` are bounded, `U` is not counted, and `P` is in
+// `PhantomData`.
+#[derive(HeapSize)]
+pub struct G2<K, V, U, P> {
+    #[heap_size]
+    pub map: HashMap<K, Vec<V>>,
+    pub opaque: U,
+    #[heap_size]
+    pub marker: PhantomData<P>,
+}
+
+#[derive(HeapSize)]
+#[heap_size]
+pub enum G3<T, U> {
+    A(Option<Box<T>>),
+    B {
+        #[heap_size(skip)]
+        u: U,
+    },
+}
+
+mod opaque_len {
+    pub fn heap_size<T: AsRef<[u8]>>(t: &T) -> usize {
+        t.as_ref().len()
+    }
+}
+
+// Fields with `with` are not inferred, the bound is given instead.
+#[derive(HeapSize)]
+pub struct G4<T> {
+    #[heap_size(with = "opaque_len", bound = "T: AsRef<[u8]>")]
+    pub a: T,
+}
+
+// The container bound replaces inferred bounds.
+#[derive(HeapSize)]
+#[heap_size(bound = "T: heapsz::HeapSize + Clone")]
+pub struct G5<T> {
+    #[heap_size]
+    pub a: Vec<T>,
+}
+
+#[derive(Clone)]
+pub struct NoHeapSize;
+
+fn main() {
+    assert_eq!(3, G1 { a: vec![0u8; 3] }.heap_size());
+
+    let g2 = G2::<u8, u8, NoHeapSize, NoHeapSize> {
+        map: HashMap::new(),
+        opaque: NoHeapSize,
+        marker: PhantomData,
+    };
+    assert_eq!(g2.map.heap_size(), g2.heap_size());
+
+    let g3 = G3::<u64, NoHeapSize>::A(Some(Box::new(1)));
+    assert_eq!(8, g3.heap_size());
+    let g3 = G3::<u64, NoHeapSize>::B { u: NoHeapSize };
+    assert_eq!(0, g3.heap_size());
+
+    assert_eq!(4, G4 { a: vec![0u8; 4] }.heap_size());
+    assert_eq!(2, G5 { a: vec![0u8; 2] }.heap_size());
+}
@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use heapsz::{kind::MemoryKind, HeapSize as _};
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+pub struct Segment {
+    #[heap_size(kind = "mmap")]
+    pub data: Vec<u8>,
+    #[heap_size]
+    pub index: Vec<u32>,
+    #[heap_size]
+    pub meta: Arc<String>,
+    #[heap_size(kind = "pinned")]
+    pub io_buf: Vec<u8>,
+    #[heap_size(kind = "mmap")]
+    pub mapped: Arc<Vec<u8>>,
+}
+
+fn main() {
+    let s = Segment {
+        data: vec![0; 4096],
+        index: vec![0; 16],
+        meta: Arc::new("segment".to_owned()),
+        io_buf: vec![0; 512],
+        mapped: Arc::new(vec![0; 64]),
+    };
+    let breakdown = s.heap_size_by_kind();
+    // The field kind overrides the shared `Arc`.
+    assert_eq!(
+        s.data.capacity() + s.mapped.heap_size(),
+        breakdown.get(MemoryKind::Mmap)
+    );
+    assert_eq!(s.io_buf.capacity(), breakdown.get(MemoryKind::Pinned));
+    assert_eq!(s.meta.heap_size(), breakdown.get(MemoryKind::Shared));
+    assert_eq!(s.index.heap_size(), breakdown.get(MemoryKind::Heap));
+    assert_eq!(s.heap_size(), breakdown.total());
+}
@@ -0,0 +1,60 @@
+#[cfg(feature = "rayon")]
+mod par {
+    use std::collections::HashMap;
+
+    use heapsz::{HeapSize as _, ParHeapSize};
+    use heapsz_derive::HeapSize;
+
+    #[derive(HeapSize)]
+    pub struct S1 {
+        #[heap_size(par)]
+        pub a: Vec<Vec<u8>>,
+        #[heap_size(par)]
+        pub b: HashMap<u64, Vec<u8>>,
+        #[heap_size]
+        pub c: Vec<u8>,
+        pub d: usize,
+    }
+
+    #[derive(HeapSize)]
+    #[heap_size]
+    pub struct S2(#[heap_size(par)] pub Vec<Vec<u8>>, pub Vec<u8>);
+
+    #[derive(HeapSize)]
+    pub enum E1 {
+        A(#[heap_size(par)] Vec<Vec<u8>>, #[heap_size] Vec<u8>),
+        B {
+            #[heap_size]
+            a: Vec<u8>,
+        },
+        C,
+    }
+
+    pub fn check() {
+        let a = (0..10).map(|i| vec![0u8; i]).collect::<Vec<_>>();
+        let s1 = S1 {
+            a: a.clone(),
+            b: HashMap::new(),
+            c: vec![0; 8],
+            d: 0,
+        };
+        assert_eq!(
+            a.par_heap_size() + s1.b.par_heap_size() + s1.c.heap_size(),
+            s1.par_heap_size()
+        );
+
+        let s2 = S2(a.clone(), vec![0; 8]);
+        assert_eq!(a.par_heap_size() + 8, s2.par_heap_size());
+
+        let e1 = E1::A(a.clone(), vec![0; 8]);
+        assert_eq!(a.par_heap_size() + 8, e1.par_heap_size());
+        let e1 = E1::B { a: vec![0; 8] };
+        assert_eq!(8, e1.par_heap_size());
+        assert_eq!(0, E1::C.par_heap_size());
+    }
+}
+
+fn main() {
+    #[cfg(feature = "rayon")]
+    par::check();
+}
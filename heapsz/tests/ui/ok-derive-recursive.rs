@@ -0,0 +1,51 @@
+use std::mem::size_of;
+
+use heapsz::{
+    traverse::{stops, with_max_depth, Traversal},
+    HeapSize as _,
+};
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+pub enum Expr {
+    Num(i64),
+    Neg(#[heap_size] Box<Expr>),
+    Add(#[heap_size] Box<Expr>, #[heap_size] Box<Expr>),
+}
+
+#[derive(HeapSize)]
+pub struct Node {
+    pub value: u64,
+    #[heap_size]
+    pub next: Option<Box<Node>>,
+}
+
+fn main() {
+    let mut expr = Expr::Num(1);
+    for _ in 0..100 {
+        expr = Expr::Neg(Box::new(expr));
+    }
+    let expr = Expr::Add(Box::new(expr), Box::new(Expr::Num(2)));
+    assert_eq!(102 * size_of::<Expr>(), expr.heap_size());
+
+    let partial = Traversal::new().max_depth(10).run(&expr);
+    // 10 nested `Neg`s are visited, and the 11th one is counted without its
+    // pointee.
+    assert_eq!(12 * size_of::<Expr>(), partial.heap_size);
+    assert_eq!(1, partial.stops.depth_exceeded);
+
+    let mut list = Node {
+        value: 0,
+        next: None,
+    };
+    for value in 1..=100 {
+        list = Node {
+            value,
+            next: Some(Box::new(list)),
+        };
+    }
+    let (heap_size, s) = stops(|| with_max_depth(50, || list.heap_size()));
+    assert_eq!(51 * size_of::<Node>(), heap_size);
+    assert_eq!(1, s.depth_exceeded);
+    assert_eq!(0, s.cycles);
+}
@@ -0,0 +1,40 @@
+#[cfg(feature = "derive")]
+mod derive {
+    use heapsz::{derive::HeapSize, HeapSize as _};
+
+    // A single dependency, the derive macro lives in its own module.
+    #[derive(HeapSize)]
+    pub struct S1 {
+        #[heap_size]
+        pub a: Vec<u8>,
+    }
+
+    pub fn check() {
+        assert_eq!(4, S1 { a: vec![0; 4] }.heap_size());
+    }
+}
+
+#[cfg(feature = "derive")]
+mod both {
+    // The trait from `heapsz` next to the macro from `heapsz_derive` does not
+    // clash when the `derive` feature is enabled.
+    use heapsz::HeapSize;
+    use heapsz_derive::HeapSize;
+
+    #[derive(HeapSize)]
+    pub struct S2 {
+        #[heap_size]
+        pub a: Vec<u8>,
+    }
+
+    pub fn check() {
+        assert_eq!(4, S2 { a: vec![0; 4] }.heap_size());
+    }
+}
+
+fn main() {
+    #[cfg(feature = "derive")]
+    derive::check();
+    #[cfg(feature = "derive")]
+    both::check();
+}
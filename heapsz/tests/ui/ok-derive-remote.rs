@@ -0,0 +1,92 @@
+use heapsz::HeapSize as _;
+use heapsz_derive::HeapSize;
+
+mod other_crate {
+    pub struct Meta {
+        pub name: String,
+        pub id: u64,
+    }
+
+    pub struct Wrapper<T>(pub Vec<T>, pub usize);
+
+    pub enum Value {
+        Str(String),
+        Meta { meta: Meta, len: usize },
+        Null,
+    }
+}
+
+#[derive(HeapSize)]
+#[heap_size(remote = "other_crate::Meta")]
+pub struct MetaDef {
+    #[heap_size]
+    pub name: String,
+    pub id: u64,
+}
+
+#[derive(HeapSize)]
+#[heap_size(remote = "other_crate::Wrapper")]
+pub struct WrapperDef<T>(#[heap_size] pub Vec<T>, #[heap_size(zero)] pub usize);
+
+#[derive(HeapSize)]
+#[heap_size(remote = "other_crate::Wrapper<u8>")]
+pub struct BytesDef(#[heap_size(shallow)] pub Vec<u8>, pub usize);
+
+#[derive(HeapSize)]
+#[heap_size(remote = "other_crate::Value")]
+#[heap_size(auto)]
+pub enum ValueDef {
+    Str(String),
+    Meta {
+        #[heap_size(with = "MetaDef")]
+        meta: other_crate::Meta,
+        len: usize,
+    },
+    Null,
+}
+
+#[derive(HeapSize)]
+pub struct Local {
+    #[heap_size(with = "MetaDef")]
+    pub meta: other_crate::Meta,
+    #[heap_size(with = "WrapperDef")]
+    pub wrapper: other_crate::Wrapper<String>,
+    #[heap_size(with = "ValueDef")]
+    pub value: other_crate::Value,
+}
+
+fn main() {
+    let meta = || other_crate::Meta {
+        name: "name".to_owned(),
+        id: 1,
+    };
+    assert_eq!(4, MetaDef::heap_size(&meta()));
+
+    let wrapper = other_crate::Wrapper(vec!["a".to_owned(), "bc".to_owned()], 2);
+    assert_eq!(wrapper.0.heap_size(), WrapperDef::heap_size(&wrapper));
+    assert_eq!(
+        8,
+        BytesDef::heap_size(&other_crate::Wrapper(vec![0u8; 8], 8))
+    );
+
+    let value = other_crate::Value::Meta {
+        meta: meta(),
+        len: 1,
+    };
+    assert_eq!(4, ValueDef::heap_size(&value));
+    assert_eq!(
+        3,
+        ValueDef::heap_size(&other_crate::Value::Str("abc".to_owned()))
+    );
+    assert_eq!(0, ValueDef::heap_size(&other_crate::Value::Null));
+
+    let local = Local {
+        meta: meta(),
+        wrapper,
+        value,
+    };
+    assert_eq!(
+        4 + local.wrapper.0.heap_size() + 4,
+        local.heap_size()
+    );
+}
@@ -0,0 +1,52 @@
+use std::{cell::RefCell, mem::size_of, rc::Rc};
+
+use heapsz::retained::{Entry, Graph};
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+pub struct Node {
+    #[heap_size]
+    pub children: RefCell<Vec<Rc<Node>>>,
+    #[heap_size]
+    pub payload: Vec<u8>,
+}
+
+#[derive(HeapSize)]
+pub struct Index {
+    #[heap_size]
+    pub nodes: Vec<Rc<Node>>,
+}
+
+fn main() {
+    let leaf = Rc::new(Node {
+        children: RefCell::new(vec![]),
+        payload: vec![0; 64],
+    });
+    let tree = Rc::new(Node {
+        children: RefCell::new(vec![leaf.clone()]),
+        payload: vec![0; 8],
+    });
+    let index = Index { nodes: vec![leaf] };
+
+    let mut graph = Graph::new();
+    graph.add_root("tree", &tree);
+    graph.add_root("index", &index);
+    let report = graph.report();
+
+    // The leaf is shared by the tree and the index, so neither of them
+    // retains it.
+    let children_cap = tree.children.borrow().capacity();
+    assert_eq!(
+        size_of::<Node>() + children_cap * size_of::<Rc<Node>>() + 8,
+        report.roots[0].retained
+    );
+    assert_eq!(size_of::<Rc<Node>>(), report.roots[1].retained);
+    assert_eq!(
+        Entry {
+            path: "tree.children".to_owned(),
+            retained: children_cap * size_of::<Rc<Node>>(),
+        },
+        report.fields[0]
+    );
+    assert!(report.to_string().starts_with("tree "));
+}
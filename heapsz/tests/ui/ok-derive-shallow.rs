@@ -0,0 +1,43 @@
+use std::{collections::HashMap, mem::size_of};
+
+use heapsz::HeapSize as _;
+use heapsz_derive::HeapSize;
+
+// A third-party type which does not implement `HeapSize`.
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(HeapSize)]
+pub struct Shape {
+    #[heap_size(shallow)]
+    pub points: Vec<Point>,
+    #[heap_size(shallow)]
+    pub labels: HashMap<u32, Point>,
+    #[heap_size(shallow)]
+    pub names: Vec<String>,
+}
+
+#[derive(HeapSize)]
+pub enum Geometry {
+    Shape(#[heap_size(shallow)] Box<Point>),
+    Empty,
+}
+
+fn main() {
+    let shape = Shape {
+        points: Vec::with_capacity(4),
+        labels: HashMap::new(),
+        names: vec!["name".to_owned()],
+    };
+    // Strings in `names` are not counted.
+    assert_eq!(
+        4 * size_of::<Point>() + shape.names.capacity() * size_of::<String>(),
+        shape.heap_size()
+    );
+
+    let g = Geometry::Shape(Box::new(Point { x: 0.0, y: 0.0 }));
+    assert_eq!(size_of::<Point>(), g.heap_size());
+    assert_eq!(0, Geometry::Empty.heap_size());
+}
@@ -0,0 +1,41 @@
+use std::{rc::Rc, sync::Arc};
+
+use heapsz::{
+    traverse::{Shared, Traversal},
+    HeapSize as _,
+};
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+pub struct Component {
+    #[heap_size(shared = "proportional")]
+    pub cache: Arc<Vec<u8>>,
+    #[heap_size(shared = "proportional")]
+    pub names: Rc<[String]>,
+    #[heap_size(shared = "full")]
+    pub config: Arc<Vec<u8>>,
+}
+
+fn main() {
+    let cache = Arc::new(vec![0u8; 100]);
+    let names: Rc<[String]> = Rc::from(vec![String::new(); 4]);
+    let config = Arc::new(vec![0u8; 10]);
+    let components: Vec<_> = (0..2)
+        .map(|_| Component {
+            cache: cache.clone(),
+            names: names.clone(),
+            config: config.clone(),
+        })
+        .collect();
+
+    let expected = cache.heap_size() / 3 + names.heap_size() / 3 + config.heap_size();
+    assert_eq!(expected, components[0].heap_size());
+
+    // `shared = "full"` overrides the traversal.
+    let total = Traversal::new()
+        .shared(Shared::Proportional)
+        .run(&components)
+        .heap_size;
+    let cap_bytes = components.capacity() * std::mem::size_of::<Component>();
+    assert_eq!(cap_bytes + 2 * expected, total);
+}
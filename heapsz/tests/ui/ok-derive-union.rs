@@ -0,0 +1,98 @@
+use std::mem::ManuallyDrop;
+
+use heapsz::HeapSize as _;
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+#[heap_size(active = "active_field")]
+pub union Value {
+    #[heap_size]
+    pub bytes: ManuallyDrop<Vec<u8>>,
+    #[heap_size]
+    pub text: ManuallyDrop<String>,
+    pub int: i64,
+}
+
+impl Value {
+    /// # Safety
+    ///
+    /// Values are always bytes in this test.
+    unsafe fn active_field(&self) -> &'static str {
+        "bytes"
+    }
+}
+
+mod opaque_heap_size {
+    pub fn heap_size(o: &super::Opaque) -> usize {
+        o.len
+    }
+}
+
+pub fn handle_heap_size(h: &Handle) -> usize {
+    h.0 * 2
+}
+
+// An opaque type that owns `len` bytes somewhere.
+#[derive(HeapSize)]
+#[heap_size(with = "opaque_heap_size")]
+pub struct Opaque {
+    pub len: usize,
+}
+
+#[derive(HeapSize)]
+#[heap_size(fn = "handle_heap_size")]
+pub struct Handle(pub usize);
+
+#[derive(HeapSize)]
+#[heap_size(fn = "Shape::area")]
+pub enum Shape {
+    Square(usize),
+}
+
+impl Shape {
+    fn area(&self) -> usize {
+        match self {
+            Shape::Square(a) => a * a,
+        }
+    }
+}
+
+pub fn generic_heap_size<T: AsRef<[u8]>>(g: &Generic<T>) -> usize {
+    g.0.as_ref().len()
+}
+
+#[derive(HeapSize)]
+#[heap_size(fn = "generic_heap_size", bound = "T: AsRef<[u8]>")]
+pub struct Generic<T>(pub T);
+
+#[derive(HeapSize)]
+#[heap_size(active = "active")]
+pub union Pair<T: Copy> {
+    #[heap_size]
+    pub a: T,
+    #[heap_size(zero)]
+    pub b: u64,
+}
+
+impl<T: Copy> Pair<T> {
+    /// # Safety
+    ///
+    /// Only `b` is ever initialized in this test.
+    unsafe fn active(&self) -> &str {
+        "b"
+    }
+}
+
+fn main() {
+    let mut v = Value {
+        bytes: ManuallyDrop::new(vec![0; 8]),
+    };
+    assert_eq!(8, v.heap_size());
+    unsafe { ManuallyDrop::drop(&mut v.bytes) };
+
+    assert_eq!(16, Opaque { len: 16 }.heap_size());
+    assert_eq!(6, Handle(3).heap_size());
+    assert_eq!(9, Shape::Square(3).heap_size());
+    assert_eq!(3, Generic(vec![0u8; 3]).heap_size());
+    assert_eq!(0, Pair::<u32> { b: 1 }.heap_size());
+}
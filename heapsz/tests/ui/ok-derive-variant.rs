@@ -0,0 +1,76 @@
+use std::mem::size_of;
+
+use heapsz::{retained::Graph, HeapSize as _};
+use heapsz_derive::HeapSize;
+
+pub fn pair_heap_size(a: &Vec<u8>, b: &usize) -> usize {
+    a.len() * b
+}
+
+mod named {
+    pub fn heap_size(v: &Vec<u32>) -> usize {
+        v.len()
+    }
+}
+
+#[derive(HeapSize)]
+pub enum E1 {
+    #[heap_size]
+    All(Vec<u8>, Box<u64>),
+    #[heap_size(shallow)]
+    Shallow { items: Vec<String> },
+    #[heap_size(fn = "pair_heap_size")]
+    Fn(Vec<u8>, usize),
+    #[heap_size(with = "named")]
+    With { v: Vec<u32> },
+    None(Vec<u8>),
+}
+
+#[derive(HeapSize)]
+#[heap_size]
+pub enum E2 {
+    #[heap_size(skip)]
+    Skipped(#[heap_size] Vec<u8>),
+    Kept(Vec<u8>, #[heap_size(skip)] Vec<u8>),
+}
+
+#[derive(HeapSize)]
+pub enum E3 {
+    // The variant attribute allows skipping its fields.
+    #[heap_size]
+    Partial(Vec<u8>, #[heap_size(skip)] Vec<u8>),
+    None(Vec<u8>),
+}
+
+fn main() {
+    assert_eq!(4 + 8, E1::All(vec![0; 4], Box::new(0)).heap_size());
+    let items = vec!["x".repeat(64); 2];
+    assert_eq!(
+        2 * size_of::<String>(),
+        E1::Shallow { items }.heap_size()
+    );
+    assert_eq!(12, E1::Fn(vec![0; 4], 3).heap_size());
+    assert_eq!(5, E1::With { v: vec![0; 5] }.heap_size());
+    assert_eq!(0, E1::None(vec![0; 4]).heap_size());
+
+    assert_eq!(0, E2::Skipped(vec![0; 4]).heap_size());
+    assert_eq!(4, E2::Kept(vec![0; 4], vec![0; 8]).heap_size());
+    assert_eq!(4, E3::Partial(vec![0; 4], vec![0; 8]).heap_size());
+    assert_eq!(0, E3::None(vec![0; 4]).heap_size());
+
+    // Fields are reported under their variant.
+    let all = E1::All(vec![0; 4], Box::new(0));
+    let with = E1::With { v: vec![0; 5] };
+    let mut graph = Graph::new();
+    graph.add_root("all", &all);
+    graph.add_root("with", &with);
+    let paths: Vec<_> = graph
+        .report()
+        .fields
+        .into_iter()
+        .map(|e| (e.path, e.retained))
+        .collect();
+    assert!(paths.contains(&("all.All".to_owned(), 12)));
+    assert!(paths.contains(&("all.All.0".to_owned(), 4)));
+    assert!(paths.contains(&("with.With".to_owned(), 5)));
+}
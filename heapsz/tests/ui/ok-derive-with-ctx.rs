@@ -0,0 +1,57 @@
+use std::rc::Rc;
+
+use heapsz::{
+    context::{with_context, Charged, HeapSizeWith},
+    HeapSize as _,
+};
+use heapsz_derive::HeapSize;
+
+pub struct Arena {
+    pub chunks: Vec<Vec<u8>>,
+}
+
+pub struct ArenaRef(pub usize);
+
+impl HeapSizeWith<Arena> for ArenaRef {
+    fn heap_size_with(&self, arena: &Arena) -> usize {
+        arena.chunks[self.0].capacity()
+    }
+}
+
+#[derive(HeapSize)]
+pub struct Node {
+    #[heap_size(with_ctx = "Charged")]
+    pub name: Rc<str>,
+    #[heap_size(with_ctx = "Arena")]
+    pub data: ArenaRef,
+    #[heap_size]
+    pub children: Vec<u32>,
+}
+
+fn main() {
+    let name: Rc<str> = Rc::from("node");
+    let arena = Arena {
+        chunks: vec![vec![0; 16], vec![0; 32]],
+    };
+    let nodes = vec![
+        Node {
+            name: name.clone(),
+            data: ArenaRef(0),
+            children: vec![],
+        },
+        Node {
+            name,
+            data: ArenaRef(1),
+            children: vec![],
+        },
+    ];
+
+    // Without contexts.
+    assert_eq!(0, nodes[0].heap_size());
+
+    let charged = Charged::new();
+    let bytes = with_context(&charged, || {
+        with_context(&arena, || nodes.iter().map(heapsz::HeapSize::heap_size).sum::<usize>())
+    });
+    assert_eq!("node".len() + 16 + 32, bytes);
+}
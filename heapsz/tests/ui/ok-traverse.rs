@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+
+use heapsz::{traverse::Traversal, HeapSize as _};
+use heapsz_derive::HeapSize;
+
+#[derive(HeapSize)]
+#[heap_size]
+pub struct Row {
+    pub cells: Vec<String>,
+}
+
+#[derive(HeapSize)]
+pub enum Table {
+    Rows(#[heap_size] Vec<Row>),
+    Index {
+        #[heap_size]
+        index: HashMap<u64, Row>,
+    },
+}
+
+fn main() {
+    let rows = (0..100)
+        .map(|i| Row {
+            cells: vec!["x".repeat(i % 10); 2],
+        })
+        .collect::<Vec<_>>();
+    let exact = rows.capacity() * std::mem::size_of::<Row>()
+        + rows
+            .iter()
+            .map(|r| {
+                r.cells.capacity() * std::mem::size_of::<String>()
+                    + r.cells.iter().map(String::capacity).sum::<usize>()
+            })
+            .sum::<usize>();
+    let table = Table::Rows(rows);
+
+    let partial = Traversal::new().run(&table);
+    assert!(!partial.exhausted);
+    assert_eq!(1.0, partial.visited_fraction);
+    assert_eq!(exact, partial.heap_size);
+    assert_ne!(exact, table.heap_size());
+
+    let partial = Traversal::new().max_elements(30).run(&table);
+    assert!(partial.exhausted);
+    assert!(partial.visited_fraction < 1.0);
+}